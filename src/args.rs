@@ -1,32 +1,217 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+
+use git_reviewers::config::ConfigScope;
+use git_reviewers::selection::SelectMode;
+use crate::report::OutputFormat;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-   /// Name of the person to greet
-   #[arg(short, long)]
+   /// Gerrit url of the project, e.g. ssh://user@host:29418/project, instead of the url of the git remote
+   #[arg(short, long, global = true)]
    pub url: Option<String>,
 
-   #[arg(short, long)]
+   #[arg(short, long, global = true)]
    pub change: Option<String>,
 
+   /// Output format of listings
+   #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Plain)]
+   pub format: OutputFormat,
+
+   /// Print reviewer changes instead of sending them
+   #[arg(long, global = true)]
+   pub dry_run: bool,
+
    #[clap(flatten)]
    pub verbose: clap_verbosity_flag::Verbosity,
+
+   #[command(subcommand)]
+   pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+   /// Show current reviewers of the change
+   Reviewers,
+   /// Show your recent changes
+   Recent,
+   /// Show unsatisfied submit requirements of the change and who can satisfy them
+   Requirements,
+   /// Suggest reviewers for the change from reviewers of your recent changes
+   Suggest,
+   /// Manage reviewer groups
+   Group {
+      /// Operate on the global config instead of the repository one
+      #[arg(short, long)]
+      global: bool,
+
+      #[command(subcommand)]
+      action: GroupAction,
+   },
+   /// Push HEAD for review with reviewers attached
+   Push {
+      /// Remote to push to
+      #[arg(long, default_value = "origin")]
+      remote: String,
+      /// Target branch, default to the upstream branch of HEAD
+      #[arg(short, long)]
+      branch: Option<String>,
+      /// Add reviewers chosen from the group
+      #[arg(short, long = "group")]
+      groups: Vec<String>,
+      /// Add the user as reviewer
+      #[arg(short, long = "reviewer")]
+      reviewers: Vec<String>,
+      /// CC the user
+      #[arg(long)]
+      cc: Vec<String>,
+      /// Add the N most recent reviewers of your changes
+      #[arg(long, default_value_t = 0)]
+      recent: usize,
+      /// Pass reviewers as push options instead of in the refspec
+      #[arg(short = 'o', long)]
+      push_options: bool,
+      /// Write the reviewers into the commit message by amending HEAD
+      #[arg(long)]
+      write_trailers: bool,
+   },
+   /// Show or change reviewers of every open change of the local stack
+   Stack {
+      /// Base of the stack
+      #[arg(long, default_value = "@{upstream}")]
+      upstream: String,
+      /// Find the stack by Gerrit related changes of the change instead
+      #[arg(long)]
+      related: bool,
+      /// Remote of the Gerrit server
+      #[arg(long, default_value = "origin")]
+      remote: String,
+      /// Add the user as reviewer of every change
+      #[arg(short, long)]
+      add: Vec<String>,
+      /// Remove the user from reviewers of every change
+      #[arg(short = 'd', long)]
+      remove: Vec<String>,
+      /// Add reviewers chosen from the group to every change
+      #[arg(short, long = "group")]
+      groups: Vec<String>,
+   },
+   /// Show or change reviewers of every open change of a topic
+   Topic {
+      topic: String,
+      /// Remote of the Gerrit server
+      #[arg(long, default_value = "origin")]
+      remote: String,
+
+      #[command(subcommand)]
+      action: TopicAction,
+   },
+   /// Show the latest reviewer changes made by this tool
+   History {
+      /// Number of entries to show
+      #[arg(short = 'n', long, default_value_t = 10)]
+      count: usize,
+   },
+   /// Revert the latest reviewer change made by this tool
+   Undo,
+   /// Add reviewers declared by Reviewer/Cc trailers of a commit to its change
+   ApplyTrailers {
+      /// Commit to read the trailers from
+      #[arg(default_value = "HEAD")]
+      commit: String,
+      /// Remote of the Gerrit server
+      #[arg(long, default_value = "origin")]
+      remote: String,
+   },
+   /// Show open changes of others where you are a reviewer or in the attention set
+   Inbox {
+      /// Remote of the Gerrit server
+      #[arg(long, default_value = "origin")]
+      remote: String,
+   },
+   /// Show review activity of each reviewer on changes updated in a date range
+   Stats {
+      /// First day, YYYY-MM-DD, default to 30 days ago
+      #[arg(long)]
+      since: Option<String>,
+      /// Last day, YYYY-MM-DD, default to today
+      #[arg(long)]
+      until: Option<String>,
+      /// Project to mine, default to the project of the remote
+      #[arg(short, long)]
+      project: Option<String>,
+      /// Only changes targeting the branch
+      #[arg(short, long)]
+      branch: Option<String>,
+      /// Remote of the Gerrit server
+      #[arg(long, default_value = "origin")]
+      remote: String,
+      /// Maximum number of changes to mine
+      #[arg(long, default_value_t = 1000)]
+      max: usize,
+   },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum GroupAction {
+   /// Show groups of all scopes
+   List,
+   /// Create a new group, members may be `@group` or `gerrit:group`
+   Create { name: String, members: Vec<String> },
+   /// Rename a group
+   Rename { name: String, new_name: String },
+   /// Delete a group
+   Delete { name: String },
+   /// Add members to a group
+   Add { name: String, members: Vec<String> },
+   /// Remove members from a group
+   Remove { name: String, members: Vec<String> },
+   /// Copy a group to another scope
+   Copy { name: String, to: ConfigScope },
+   /// Create a group from the reviewers of the change
+   Import { name: String },
+   /// Set how reviewers are chosen when the group is added
+   Mode {
+      name: String,
+      mode: SelectMode,
+      /// Number of reviewers to choose
+      #[arg(short = 'n', long, default_value_t = 1)]
+      count: usize,
+   },
+   /// Show the users of a group, following nested groups
+   Expand {
+      name: String,
+      /// Query members of Gerrit groups from the server
+      #[arg(long)]
+      live: bool,
+   },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum TopicAction {
+   /// Show reviewers of each change
+   List,
+   /// Add reviewers to each change
+   Add { reviewers: Vec<String> },
+   /// Remove all reviewers of each change
+   Clear,
 }
 
 pub fn parse() -> Args {
     let args = Args::parse();
-    
-    if let Some(s) = &args.url {
-        println!("url: {}", s);
-    }
 
-    if let Some(s) = &args.change {
-        println!("change: {}", s);
+    // Keep machine-readable output clean
+    if args.format == OutputFormat::Plain {
+        if let Some(s) = &args.url {
+            println!("url: {}", s);
+        }
+
+        if let Some(s) = &args.change {
+            println!("change: {}", s);
+        }
     }
 
     // TODO semantic check
 
     args
 }
-
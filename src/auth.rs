@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use log::{debug, warn};
+use dirs::home_dir;
+
+use crate::os;
+use crate::git;
+use crate::config;
+
+const DEFAULT_ORDER: &str = "credential,netrc,cookies,env";
+const DEFAULT_TOKEN_ENV: &str = "GERRIT_HTTP_PASSWORD";
+const DEFAULT_USER_ENV: &str = "GERRIT_HTTP_USER";
+
+/// Where the credentials of a Gerrit host are looked up
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AuthSource {
+    Credential,
+    Netrc,
+    Cookies,
+    Env,
+}
+
+impl AuthSource {
+    pub fn parse(s: &str) -> Option<AuthSource> {
+        match s.trim() {
+            "credential" => Some(AuthSource::Credential),
+            "netrc" => Some(AuthSource::Netrc),
+            "cookies" => Some(AuthSource::Cookies),
+            "env" => Some(AuthSource::Env),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuthSource::Credential => "credential",
+            AuthSource::Netrc => "netrc",
+            AuthSource::Cookies => "cookies",
+            AuthSource::Env => "env",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum Secret {
+    Basic { username: String, password: String },
+    Cookie(String),
+}
+
+#[derive(Clone)]
+pub struct Credential {
+    pub source: AuthSource,
+    pub secret: Secret,
+}
+
+impl Credential {
+    /// curl config read from stdin, so the secret never shows up in the arguments
+    pub fn to_curl_config(&self) -> String {
+        match &self.secret {
+            Secret::Basic { username, password } =>
+                format!("user = \"{}\"\n", curl_escape(&format!("{}:{}", username, password))),
+            Secret::Cookie(cookie) => format!("cookie = \"{}\"\n", curl_escape(cookie)),
+        }
+    }
+}
+
+// Never print the secret itself
+impl fmt::Display for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.secret {
+            Secret::Basic { username, .. } => write!(f, "{} from {}", username, self.source.name()),
+            Secret::Cookie(_) => write!(f, "cookie from {}", self.source.name()),
+        }
+    }
+}
+
+fn curl_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Sources in the order of the `gerreviewer.httpAuth` setting
+pub fn get_order() -> Vec<AuthSource> {
+    let order = config::get_setting("httpAuth").unwrap_or_else(|| DEFAULT_ORDER.to_string());
+    let mut sources = Vec::new();
+    for s in order.split(',') {
+        match AuthSource::parse(s) {
+            Some(src) => sources.push(src),
+            None => warn!("Unknown auth source: {}", s.trim()),
+        }
+    }
+    sources
+}
+
+pub fn describe_order() -> String {
+    get_order().iter().map(|s| s.name()).collect::<Vec<&str>>().join(", ")
+}
+
+// Looked up once per host, git credential fill may run a slow helper
+static CACHE: OnceLock<Mutex<HashMap<String, Option<Credential>>>> = OnceLock::new();
+
+/// Credential of the first source which has one for the host
+pub fn get_credential(scheme: &str, host: &str, username: Option<&str>) -> Option<Credential> {
+    let cache = CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(c) = cache.lock().unwrap().get(host) {
+        return c.clone();
+    }
+
+    let mut found = None;
+    for source in get_order() {
+        let secret = match source {
+            AuthSource::Credential => from_git_credential(scheme, host, username),
+            AuthSource::Netrc => from_netrc(host),
+            AuthSource::Cookies => from_gitcookies(host),
+            AuthSource::Env => from_env(username),
+        };
+        if let Some(secret) = secret {
+            found = Some(Credential { source, secret });
+            break;
+        }
+        debug!("No credential of {} in {}", host, source.name());
+    }
+    cache.lock().unwrap().insert(host.to_string(), found.clone());
+    found
+}
+
+fn from_git_credential(scheme: &str, host: &str, username: Option<&str>) -> Option<Secret> {
+    let mut input = format!("protocol={}\nhost={}\n", scheme, host);
+    if let Some(u) = username {
+        input.push_str(&format!("username={}\n", u));
+    }
+    input.push('\n');
+
+    // Fail instead of prompting on the terminal
+    let cmd = vec!["git", "credential", "fill"];
+    let out = os::run_command_input(cmd, &input, &[("GIT_TERMINAL_PROMPT", "0"), ("GIT_ASKPASS", "true")]).ok()?;
+
+    let mut user = None;
+    let mut password = None;
+    for line in out.lines() {
+        match line.split_once('=') {
+            Some(("username", v)) => user = Some(v.to_string()),
+            Some(("password", v)) => password = Some(v.to_string()),
+            _ => {},
+        }
+    }
+    match (user, password) {
+        (Some(username), Some(password)) if !password.is_empty() => Some(Secret::Basic { username, password }),
+        _ => None,
+    }
+}
+
+fn get_netrc_path() -> Option<PathBuf> {
+    match std::env::var("NETRC") {
+        Ok(p) => Some(PathBuf::from(p)),
+        Err(_) => home_dir().map(|h| h.join(".netrc")),
+    }
+}
+
+fn from_netrc(host: &str) -> Option<Secret> {
+    let contents = fs::read_to_string(get_netrc_path()?).ok()?;
+    parse_netrc(&contents, host)
+}
+
+/// Login of `machine <host>`, or of `default` if no machine matches
+pub fn parse_netrc(contents: &str, host: &str) -> Option<Secret> {
+    let mut tokens = contents.split_whitespace();
+    let mut matched = None;
+    let mut default = None;
+    // (is default entry, login, password) of the entry being parsed
+    let mut cur: Option<(bool, Option<String>, Option<String>)> = None;
+
+    let mut finish = |cur: Option<(bool, Option<String>, Option<String>)>| {
+        if let Some((is_default, Some(username), Some(password))) = cur {
+            let secret = Secret::Basic { username, password };
+            if is_default {
+                default.get_or_insert(secret);
+            } else {
+                matched.get_or_insert(secret);
+            }
+        }
+    };
+
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" => {
+                finish(cur.take());
+                if tokens.next() == Some(host) {
+                    cur = Some((false, None, None));
+                }
+            },
+            "default" => {
+                finish(cur.take());
+                cur = Some((true, None, None));
+            },
+            "login" => {
+                let v = tokens.next().map(|s| s.to_string());
+                if let Some(c) = cur.as_mut() {
+                    c.1 = v;
+                }
+            },
+            "password" => {
+                let v = tokens.next().map(|s| s.to_string());
+                if let Some(c) = cur.as_mut() {
+                    c.2 = v;
+                }
+            },
+            "account" | "macdef" => {
+                tokens.next();
+            },
+            _ => {},
+        }
+    }
+    finish(cur.take());
+    matched.or(default)
+}
+
+fn get_gitcookies_path() -> Option<PathBuf> {
+    match git::git_config_get_value("http", "cookiefile") {
+        Ok(p) => match p.strip_prefix("~/") {
+            Some(rest) => home_dir().map(|h| h.join(rest)),
+            None => Some(PathBuf::from(p)),
+        },
+        Err(_) => home_dir().map(|h| h.join(".gitcookies")),
+    }
+}
+
+fn from_gitcookies(host: &str) -> Option<Secret> {
+    let contents = fs::read_to_string(get_gitcookies_path()?).ok()?;
+    parse_gitcookies(&contents, host)
+}
+
+/// Cookies of the host in the Netscape cookie file format
+pub fn parse_gitcookies(contents: &str, host: &str) -> Option<Secret> {
+    let mut cookies = Vec::new();
+    for line in contents.lines() {
+        // Lines of HttpOnly cookies start with "#HttpOnly_"
+        let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+        if line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 7 {
+            continue;
+        }
+        let domain = fields[0];
+        let matches = match domain.strip_prefix('.') {
+            Some(d) => host == d || host.ends_with(domain),
+            None => host == domain,
+        };
+        if matches {
+            cookies.push(format!("{}={}", fields[5], fields[6]));
+        }
+    }
+    if cookies.is_empty() {
+        None
+    } else {
+        Some(Secret::Cookie(cookies.join("; ")))
+    }
+}
+
+fn from_env(username: Option<&str>) -> Option<Secret> {
+    let token_env = config::get_setting("httpTokenEnv").unwrap_or_else(|| DEFAULT_TOKEN_ENV.to_string());
+    let password = std::env::var(token_env).ok().filter(|p| !p.is_empty())?;
+    let username = match std::env::var(DEFAULT_USER_ENV) {
+        Ok(u) if !u.is_empty() => u,
+        _ => username?.to_string(),
+    };
+    Some(Secret::Basic { username, password })
+}
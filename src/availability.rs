@@ -0,0 +1,163 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use log::{debug, warn};
+use regex::Regex;
+use chrono::{Local, NaiveDate};
+
+use crate::config;
+use crate::gerrit_if;
+use crate::gerrit_if::GitUrl;
+use crate::users::GerritUser;
+use crate::candidates::Skipped;
+use crate::pool;
+
+// Account status messages which mean the user is not reviewing
+const DEFAULT_AWAY_PATTERN: &str = r"(?i)\b(away|ooo|out of office|vacation|holiday|leave)\b";
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Local out-of-office entry, configured as `gerreviewer.away`
+/// with the value `<username> [<from>..<to>]`, dates are inclusive
+struct AwayEntry {
+    username: String,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+impl AwayEntry {
+    fn parse(s: &str) -> Option<AwayEntry> {
+        let mut fields = s.split_whitespace();
+        let username = fields.next()?.to_string();
+        let (from, to) = match fields.next() {
+            Some(range) => {
+                let (from, to) = range.split_once("..")?;
+                let parse = |d: &str| {
+                    if d.is_empty() {
+                        Ok(None)
+                    } else {
+                        NaiveDate::parse_from_str(d, DATE_FORMAT).map(Some)
+                    }
+                };
+                (parse(from).ok()?, parse(to).ok()?)
+            },
+            None => (None, None),
+        };
+        Some(AwayEntry { username, from, to })
+    }
+
+    fn covers(&self, date: NaiveDate) -> bool {
+        self.from.is_none_or(|from| from <= date) && self.to.is_none_or(|to| date <= to)
+    }
+
+    fn reason(&self) -> String {
+        match self.to {
+            Some(to) => format!("away until {}", to.format(DATE_FORMAT)),
+            None => "away".to_string(),
+        }
+    }
+}
+
+/// Tells whether users are available for review, from the local
+/// out-of-office list and the status of their Gerrit accounts
+pub struct Availability<'a> {
+    conn: &'a GitUrl,
+    away: Vec<AwayEntry>,
+    away_pattern: Regex,
+    today: NaiveDate,
+    status_cache: RefCell<HashMap<String, Option<String>>>,
+}
+
+impl<'a> Availability<'a> {
+    pub fn new(conn: &'a GitUrl) -> Self {
+        let mut away = Vec::new();
+        for s in config::get_settings("away") {
+            match AwayEntry::parse(&s) {
+                Some(e) => away.push(e),
+                None => warn!("Invalid gerreviewer.away: {}", s),
+            }
+        }
+
+        let pattern = config::get_setting("awayPattern").unwrap_or(DEFAULT_AWAY_PATTERN.to_string());
+        let away_pattern = Regex::new(&pattern).unwrap_or_else(|e| {
+            warn!("Invalid gerreviewer.awayPattern: {}", e);
+            Regex::new(DEFAULT_AWAY_PATTERN).unwrap()
+        });
+
+        Self {
+            conn,
+            away,
+            away_pattern,
+            today: Local::now().date_naive(),
+            status_cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn account_status(&self, user: &GerritUser) -> Option<String> {
+        if let Some(status) = self.status_cache.borrow().get(&user.account()) {
+            return status.clone();
+        }
+        let status = match gerrit_if::get_account_status(self.conn, user) {
+            Ok(s) => s,
+            Err(e) => {
+                debug!("No account status of {}: {}", user, e);
+                None
+            },
+        };
+        self.status_cache.borrow_mut().insert(user.account(), status.clone());
+        status
+    }
+
+    /// Look up the account status of the users concurrently
+    pub fn prefetch(&self, users: &[GerritUser]) {
+        if !self.conn.is_http() {
+            return;
+        }
+        let missing: Vec<GerritUser> = users.iter()
+            .filter(|u| !self.status_cache.borrow().contains_key(&u.account()))
+            .cloned()
+            .collect();
+        let conn = self.conn;
+        let statuses = pool::map(missing, pool::jobs(), |u| {
+            let status = gerrit_if::get_account_status(conn, &u).unwrap_or_else(|e| {
+                debug!("No account status of {}: {}", u, e);
+                None
+            });
+            (u.account(), status)
+        });
+        self.status_cache.borrow_mut().extend(statuses);
+    }
+
+    /// Reason why the user is unavailable, None if available
+    pub fn check(&self, user: &GerritUser) -> Option<String> {
+        for e in &self.away {
+            if e.username == user.account() && e.covers(self.today) {
+                return Some(e.reason());
+            }
+        }
+        match self.account_status(user) {
+            Some(status) if self.away_pattern.is_match(&status) => Some(status),
+            _ => None,
+        }
+    }
+
+    /// Split available users from unavailable ones, which are returned with the reason
+    pub fn partition(&self, users: Vec<GerritUser>) -> (Vec<GerritUser>, Vec<Skipped>) {
+        self.prefetch(&users);
+        let mut kept = Vec::new();
+        let mut skipped = Vec::new();
+        for u in users {
+            match self.check(&u) {
+                Some(reason) => skipped.push(Skipped { user: u, reason }),
+                None => kept.push(u),
+            }
+        }
+        (kept, skipped)
+    }
+
+    /// User description flagged with the reason of unavailability
+    pub fn describe(&self, user: &GerritUser) -> String {
+        match self.check(user) {
+            Some(reason) => format!("{} (unavailable: {})", user.as_string(), reason),
+            None => user.as_string(),
+        }
+    }
+}
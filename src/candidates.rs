@@ -0,0 +1,182 @@
+use log::{debug, warn};
+use regex::Regex;
+
+use crate::config;
+use crate::gerrit_if;
+use crate::gerrit_if::GerritChange;
+use crate::users::GerritUser;
+use crate::availability::Availability;
+use crate::pool;
+
+// Matched against usernames and the local part of emails of service accounts,
+// domains like ci.example.com say nothing about the user
+const DEFAULT_BOT_PATTERN: &str = r"(?i)(^|[-_.])(bot|ci|jenkins|zuul|service)([-_.]|$)";
+
+/// Removes users who must not be suggested or added as reviewers of the change:
+/// the owner and uploader, current reviewers, service accounts matching
+/// `gerreviewer.botPattern` and users listed in `gerreviewer.blocked`
+pub struct CandidateFilter {
+    excluded: Vec<(GerritUser, &'static str)>,
+    bot_patterns: Vec<Regex>,
+    blocked: Vec<String>,
+}
+
+impl CandidateFilter {
+    pub fn new(change: &GerritChange) -> Result<Self, String> {
+        let mut excluded = Vec::new();
+        let (owners, reviewers) = pool::join(
+            || gerrit_if::get_change_owners(change),
+            || gerrit_if::get_reviewers(change),
+        );
+        let owners = owners?;
+        if let Some(owner) = owners.first() {
+            excluded.push((owner.clone(), "owner of the change"));
+        }
+        for uploader in owners.iter().skip(1) {
+            excluded.push((uploader.clone(), "uploader of the change"));
+        }
+        for r in reviewers? {
+            excluded.push((r, "already a reviewer"));
+        }
+        Ok(Self::with_excluded(excluded))
+    }
+
+    /// Filter for a change not uploaded yet
+    pub fn for_owner(owner: Option<GerritUser>) -> Self {
+        let excluded = owner.into_iter().map(|o| (o, "owner of the change")).collect();
+        Self::with_excluded(excluded)
+    }
+
+    fn with_excluded(excluded: Vec<(GerritUser, &'static str)>) -> Self {
+        let mut patterns = config::get_settings("botPattern");
+        if patterns.is_empty() {
+            patterns.push(DEFAULT_BOT_PATTERN.to_string());
+        }
+        let mut bot_patterns = Vec::new();
+        for p in patterns {
+            match Regex::new(&p) {
+                Ok(re) => bot_patterns.push(re),
+                Err(e) => warn!("Invalid gerreviewer.botPattern {}: {}", p, e),
+            }
+        }
+
+        Self {
+            excluded,
+            bot_patterns,
+            blocked: config::get_settings("blocked"),
+        }
+    }
+
+    /// Reason why the user is not a candidate, None if the user is
+    pub fn check(&self, user: &GerritUser) -> Option<String> {
+        for (u, reason) in &self.excluded {
+            if u.same_account(user) {
+                return Some(reason.to_string());
+            }
+        }
+        if self.blocked.iter().any(|b| *b == user.username || *b == user.email) {
+            return Some("blocked".to_string());
+        }
+        let local_part = |s: &str| s.split('@').next().unwrap_or_default().to_string();
+        // Usernames may be emails too
+        let names = [local_part(&user.username), local_part(&user.email)];
+        for re in &self.bot_patterns {
+            if names.iter().any(|n| !n.is_empty() && re.is_match(n)) {
+                return Some("service account".to_string());
+            }
+        }
+        None
+    }
+
+    pub fn apply(&self, users: Vec<GerritUser>) -> Vec<GerritUser> {
+        users.into_iter().filter(|u| {
+            match self.check(u) {
+                Some(reason) => {
+                    debug!("Filter out {}: {}", u, reason);
+                    false
+                },
+                None => true,
+            }
+        }).collect()
+    }
+
+    /// Like apply, but also returns who is skipped and why
+    pub fn partition(&self, users: Vec<GerritUser>) -> (Vec<GerritUser>, Vec<Skipped>) {
+        let mut kept = Vec::new();
+        let mut skipped = Vec::new();
+        for u in users {
+            match self.check(&u) {
+                Some(reason) => skipped.push(Skipped { user: u, reason }),
+                None => kept.push(u),
+            }
+        }
+        (kept, skipped)
+    }
+}
+
+/// User left out of the reviewers, with the reason
+#[derive(Debug)]
+pub struct Skipped {
+    pub user: GerritUser,
+    pub reason: String,
+}
+
+impl Skipped {
+    pub fn describe(&self) -> String {
+        format!("Skip {}: {}", self.user, self.reason)
+    }
+}
+
+/// User with the state of the user in the listing, e.g. REVIEWER or CANDIDATE
+pub struct UserRow {
+    pub user: GerritUser,
+    pub state: String,
+    pub score: Option<i64>,
+    pub reasons: Vec<String>,
+}
+
+impl UserRow {
+    pub fn new(user: GerritUser, state: &str) -> Self {
+        Self {
+            user,
+            state: state.to_string(),
+            score: None,
+            reasons: Vec::new(),
+        }
+    }
+}
+
+/// Candidates from reviewers of the user's recent changes, scored by the
+/// number of those changes they reviewed
+pub fn suggest(change: &GerritChange) -> Result<Vec<UserRow>, String> {
+    let user = match &change.conn.username {
+        Some(u) => GerritUser::from_str(u),
+        None => return Ok(Vec::new()),
+    };
+    let (filter, counts) = pool::join(
+        || CandidateFilter::new(change),
+        || gerrit_if::get_user_recent_reviewer_counts(&change.conn, &user),
+    );
+    let filter = filter?;
+    let counts: Vec<(GerritUser, usize)> = counts?.into_iter()
+        .filter(|(u, _)| filter.check(u).is_none())
+        .collect();
+
+    let availability = Availability::new(&change.conn);
+    let users: Vec<GerritUser> = counts.iter().map(|(u, _)| u.clone()).collect();
+    availability.prefetch(&users);
+
+    let mut rows = Vec::new();
+    for (u, count) in counts {
+        let mut row = UserRow::new(u, "CANDIDATE");
+        row.score = Some(count as i64);
+        row.reasons.push(format!("reviewed {} of your recent changes", count));
+        if let Some(reason) = availability.check(&row.user) {
+            row.state = "UNAVAILABLE".to_string();
+            row.reasons.push(reason);
+        }
+        rows.push(row);
+    }
+    rows.sort_by_key(|r| (r.state != "CANDIDATE", -r.score.unwrap_or(0)));
+    Ok(rows)
+}
@@ -1,31 +1,48 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{Write, Read};
 use std::path::{Path, PathBuf};
-use log::trace;
+use std::sync::OnceLock;
+use std::time::Duration;
+use log::{trace, warn};
 use linked_hash_set::LinkedHashSet;
 use regex::Regex;
 use dirs::home_dir;
 
-use crate::groups::{UserGroups, GerritUserGroup};
-use crate::users::GerritUser;
-use crate::users;
+use crate::groups::{UserGroups, GerritUserGroup, GroupMember, GroupRef};
+use crate::groups;
+use crate::users::UserIdentifier;
 use crate::git;
+use crate::selection::SelectMode;
 
-const CONFIG_NAME: &'static str = "gerreviewer.ini";
+const CONFIG_NAME: &str = "gerreviewer.ini";
 
+// Section of the tool settings in git config, e.g. gerreviewer.away
+const SETTINGS_SECTION: &str = "gerreviewer";
+
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_RETRIES: u32 = 2;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
 pub enum ConfigScope {
     Local,
     Global,
 }
 
+impl ConfigScope {
+    pub fn other(&self) -> ConfigScope {
+        match self {
+            ConfigScope::Local => ConfigScope::Global,
+            ConfigScope::Global => ConfigScope::Local,
+        }
+    }
+}
+
 pub struct GroupsConfig {
-    scope: ConfigScope,
+    pub scope: ConfigScope,
     pub file_path: String,
     pub groups: UserGroups,
 }
 
-pub struct RecentUsersConfig;
-
 impl GroupsConfig {
     // TODO, new, get, save, set, clear
     pub fn new(scope: ConfigScope) -> Self {
@@ -33,13 +50,13 @@ impl GroupsConfig {
         let file_path = get_config_path(&scope);
 
         let mut config = Self {
-            scope: scope,
+            scope,
             file_path: file_path.to_string(),
             groups: LinkedHashSet::new(),
         };
 
-        // Load 
-        if Path::new(&mut config.file_path).exists() {
+        // Load
+        if Path::new(&config.file_path).exists() {
             let mut contents = String::new();
             let mut file = File::open(config.file_path.clone()).unwrap();
             
@@ -56,47 +73,62 @@ impl GroupsConfig {
 
     fn load(&mut self, contents: String) {
         trace!("Load config");
-        let group_name_matcher = Regex::new(r"\[([[:word:]]+)\]").unwrap();
+        let group_name_matcher = Regex::new(r"^\s*\[([[:word:]]+)\]\s*$").unwrap();
+        let option_matcher = Regex::new(r"^\s*([[:word:]-]+)\s*=\s*(.*?)\s*$").unwrap();
 
         let lines = contents.lines();
-        let mut has_cur_group = false;
-        let mut cur_group = GerritUserGroup {
-            name: String::new(),
-            users: LinkedHashSet::new(),
-        };
-        'lines: for line in lines {
-            match group_name_matcher.captures(line) {
-                Some(caps) => {
-                    let group_name = caps.get(1).unwrap().as_str();
-                    cur_group.name = group_name.to_string();
-                    has_cur_group = true;
-                    continue;
-                },
-                None => (),
-            };
-
-            if !has_cur_group {
+        let mut cur_group: Option<GerritUserGroup> = None;
+        for line in lines {
+            if let Some(caps) = group_name_matcher.captures(line) {
+                if let Some(g) = cur_group.take() {
+                    self.insert_loaded(g);
+                }
+                let group_name = caps.get(1).unwrap().as_str();
+                cur_group = Some(GerritUserGroup::new(group_name));
                 continue;
             }
 
-            // parse user list
-            let user_strs = line.split(",");
-            if user_strs.clone().count() == 0 {
+            let group = match cur_group.as_mut() {
+                Some(g) => g,
+                None => continue,
+            };
+
+            // parse group option, e.g. "mode = round-robin"
+            if let Some(caps) = option_matcher.captures(line) {
+                let key = caps.get(1).unwrap().as_str();
+                let value = caps.get(2).unwrap().as_str();
+                if !group.set_option(key, value) {
+                    warn!("Invalid option of group {}: {}", group.name, line.trim());
+                }
                 continue;
             }
 
-            cur_group.users.clear();
-            'users: for _username in user_strs {
-                let username = _username.trim();
-                if !users::is_valid_username(username) {
-                    continue 'users;
+            // parse user list, members may be quoted
+            for member in split_members(line) {
+                // Empty groups are saved with an empty member line
+                if member.is_empty() {
+                    continue;
+                }
+                if let Some(r) = GroupRef::parse(&member) {
+                    group.includes.insert(r);
+                    continue;
+                }
+                match UserIdentifier::parse(&member) {
+                    Some(id) => {
+                        group.users.insert(id.to_user());
+                    },
+                    None => warn!("Invalid member of group {}: {}", group.name, member),
                 }
-                cur_group.users.insert(GerritUser::from_str(username));
             }
-            trace!("Parsed group: {}", cur_group.name);
-            self.groups.insert(cur_group.clone());
-            has_cur_group = false;
         }
+        if let Some(g) = cur_group.take() {
+            self.insert_loaded(g);
+        }
+    }
+
+    fn insert_loaded(&mut self, group: GerritUserGroup) {
+        trace!("Parsed group: {}", group.name);
+        self.groups.insert(group);
     }
 
     pub fn clear(&mut self) {
@@ -110,12 +142,110 @@ impl GroupsConfig {
         }
     }
 
+    pub fn get(&self, name: &str) -> Option<&GerritUserGroup> {
+        self.groups.iter().find(|g| g.name == name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn insert_group(&mut self, group: GerritUserGroup) -> Result<(), String> {
+        if !groups::is_valid_group_name(&group.name) {
+            return Err(format!("Invalid group name: {}", group.name));
+        }
+        if self.contains(&group.name) {
+            return Err(format!("Group {} already exists in {}", group.name, self.file_path));
+        }
+        self.groups.insert(group);
+        Ok(())
+    }
+
+    pub fn delete_group(&mut self, name: &str) -> Result<(), String> {
+        let found = self.get(name).cloned();
+        match found {
+            Some(g) => {
+                self.groups.remove(&g);
+                Ok(())
+            },
+            None => Err(self.not_found(name)),
+        }
+    }
+
+    pub fn rename_group(&mut self, name: &str, new_name: &str) -> Result<(), String> {
+        if !groups::is_valid_group_name(new_name) {
+            return Err(format!("Invalid group name: {}", new_name));
+        }
+        if self.contains(new_name) {
+            return Err(format!("Group {} already exists in {}", new_name, self.file_path));
+        }
+        self.update_group(name, |g| g.name = new_name.to_string())
+    }
+
+    pub fn set_mode(&mut self, name: &str, mode: SelectMode, count: usize) -> Result<(), String> {
+        if count == 0 {
+            return Err("Count must be at least 1".to_string());
+        }
+        self.update_group(name, |g| {
+            g.mode = mode;
+            g.pick = count;
+        })
+    }
+
+    pub fn add_members(&mut self, name: &str, members: Vec<GroupMember>) -> Result<(), String> {
+        let mut members = Some(members);
+        self.update_group(name, |g| {
+            for m in members.take().unwrap_or_default() {
+                g.insert_member(m);
+            }
+        })
+    }
+
+    /// Remove the members, returns a note on each one which is not a member
+    pub fn remove_members(&mut self, name: &str, members: &[GroupMember]) -> Result<Vec<String>, String> {
+        let mut notes = Vec::new();
+        self.update_group(name, |g| {
+            for m in members {
+                if !g.remove_member(m) {
+                    notes.push(format!("{} is not a member of {}", m, g.name));
+                }
+            }
+        })?;
+        Ok(notes)
+    }
+
+    // LinkedHashSet has no get_mut, rebuild the set to keep the group order
+    fn update_group<F>(&mut self, name: &str, mut f: F) -> Result<(), String>
+    where
+        F: FnMut(&mut GerritUserGroup),
+    {
+        if !self.contains(name) {
+            return Err(self.not_found(name));
+        }
+        let mut updated = LinkedHashSet::new();
+        for mut g in self.groups.drain() {
+            if g.name == name {
+                f(&mut g);
+            }
+            updated.insert(g);
+        }
+        self.groups = updated;
+        Ok(())
+    }
+
+    fn not_found(&self, name: &str) -> String {
+        format!("Group {} not found in {}", name, self.file_path)
+    }
+
     pub fn save(&self) -> Result<(), std::io::Error> {
         let mut s = String::new();
         for g in &self.groups {
-            s.push_str(&self.to_config_string(&g));
+            s.push_str(&self.to_config_string(g));
             s.push_str("\n\n");
         }
+        if let Some(dir) = Path::new(&self.file_path).parent() {
+            fs::create_dir_all(dir)?;
+        }
         let mut file = File::create(self.file_path.clone())?;
         file.write_all(s.as_bytes())?;
         trace!("Saved {}", self.file_path);
@@ -125,18 +255,105 @@ impl GroupsConfig {
     fn to_config_string(&self, g: &GerritUserGroup) -> String {
         let mut ret = format!("[{}]\n", &g.name);
         ret.push_str("  ");
-        let num = g.users.len();
-        for (pos, u) in g.users.iter().enumerate() {
-            // TODO check if contains space
-            ret.push_str(&u.username);
-            if pos != (num - 1) {
-                ret.push_str(", ");
-            }
+        let members: Vec<String> = g.users.iter()
+            .map(|u| quote_member(&u.to_identifier().to_string()))
+            .chain(g.includes.iter().map(|r| quote_member(&r.to_string())))
+            .collect();
+        ret.push_str(&members.join(", "));
+        for (key, value) in g.options() {
+            ret.push_str(&format!("\n  {} = {}", key, value));
         }
-        return ret;
+        ret
     }
 }
 
+/// Quote the member if it is not a plain identifier, e.g. `"Doe, John <john@example.com>"`
+pub fn quote_member(member: &str) -> String {
+    let plain = Regex::new(r"^[\p{L}\p{N}._@+:-]+$").unwrap();
+    if plain.is_match(member) {
+        return member.to_string();
+    }
+    format!("\"{}\"", member.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Split a member line at the commas outside of quotes, and unquote the members.
+/// Identifiers never start or end with spaces, so members are trimmed.
+pub fn split_members(line: &str) -> Vec<String> {
+    let mut members = Vec::new();
+    let mut cur = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if in_quotes => {
+                if let Some(next) = chars.next() {
+                    cur.push(next);
+                }
+            },
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                members.push(cur.trim().to_string());
+                cur.clear();
+            },
+            _ => cur.push(c),
+        }
+    }
+    if in_quotes {
+        warn!("Unterminated quote in: {}", line.trim());
+    }
+    members.push(cur.trim().to_string());
+    members
+}
+
+/// Setting from git config, local values override global ones
+pub fn get_setting(option: &str) -> Option<String> {
+    git::git_config_get_value(SETTINGS_SECTION, option).ok()
+}
+
+/// Multi-valued setting from git config of all scopes
+pub fn get_settings(option: &str) -> Vec<String> {
+    git::git_config_get_all(SETTINGS_SECTION, option)
+}
+
+/// Timeout and retries of commands talking to Gerrit, read once
+pub struct NetworkSettings {
+    pub timeout: Option<Duration>,
+    pub retries: u32,
+}
+
+static NETWORK: OnceLock<NetworkSettings> = OnceLock::new();
+
+pub fn get_network_settings() -> &'static NetworkSettings {
+    NETWORK.get_or_init(|| {
+        let secs = match get_setting("timeout") {
+            Some(s) => s.parse::<u64>().unwrap_or_else(|_| {
+                warn!("Invalid timeout: {}", s);
+                DEFAULT_TIMEOUT_SECS
+            }),
+            None => DEFAULT_TIMEOUT_SECS,
+        };
+        let retries = match get_setting("retries") {
+            Some(s) => s.parse::<u32>().unwrap_or_else(|_| {
+                warn!("Invalid retries: {}", s);
+                DEFAULT_RETRIES
+            }),
+            None => DEFAULT_RETRIES,
+        };
+        NetworkSettings {
+            // 0 waits forever
+            timeout: if secs == 0 { None } else { Some(Duration::from_secs(secs)) },
+            retries,
+        }
+    })
+}
+
+/// Path of a data file kept in the git directory of the repository
+pub fn get_data_path(file_name: &str) -> Option<String> {
+    let git_dir = git::get_git_directories()?;
+    let path = PathBuf::from(git_dir).join(file_name);
+    Some(path.to_str().unwrap().to_string())
+}
+
 fn get_config_path(scope: &ConfigScope) -> String {
     let dir_pathbuf = match scope {
         ConfigScope::Local => {
@@ -152,6 +369,10 @@ fn get_config_path(scope: &ConfigScope) -> String {
     path.to_str().unwrap().to_string()
 }
 
+pub fn get_group_config(scope: ConfigScope) -> GroupsConfig {
+    GroupsConfig::new(scope)
+}
+
 pub fn get_group_configs() -> Vec<GroupsConfig> {
     let local = get_local_groups();
     let global = get_global_groups();
@@ -1,27 +1,75 @@
 //use std::str;
 use url::Url;
+use chrono::NaiveDateTime;
 use regex::Regex;
 use log::{debug, info};
-use linked_hash_set::LinkedHashSet;
 
-use crate::os;
+use crate::os::{CommandRunner, CommandError};
+use crate::config;
 use crate::git;
-use crate::args::Args;
 use crate::users::GerritUser;
 use crate::users;
+use crate::plan;
+use crate::plan::ReviewerPlan;
+use crate::journal;
+use crate::auth;
+use crate::ssh;
+use crate::ssh::SshCommand;
 
 use serde_json::Value as Review;
+use serde_json::json;
 
 const QUERY_LIMIT: u32 = 10;
+// Changes per request of paginated queries
+const PAGE_SIZE: u32 = 100;
+// Open reviews counted per user at most, enough to rank the least loaded
+const OPEN_REVIEWS_CAP: usize = 2 * PAGE_SIZE as usize;
 
 // FIXME rename to connection or gerrit url
+#[derive(Clone)]
 pub struct GitUrl {
     scheme: String,
     hostname: String,
     pub username: Option<String>,
     port: Option<u16>,
     project: String,
-    raw: String,
+    pub raw: String,
+}
+
+impl GitUrl {
+    pub fn is_ssh(&self) -> bool {
+        self.scheme.eq("ssh")
+    }
+
+    pub fn is_http(&self) -> bool {
+        self.scheme.eq("http") || self.scheme.eq("https")
+    }
+
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    pub fn project(&self) -> &str {
+        &self.project
+    }
+
+    pub fn with_project(&self, project: &str) -> GitUrl {
+        let mut url = self.clone();
+        url.project = project.to_string();
+        url
+    }
+
+    fn rest_url(&self, path: &str) -> String {
+        let port = match self.port {
+            Some(p) if !(self.scheme.eq("http") && p == 80 || self.scheme.eq("https") && p == 443) => format!(":{}", p),
+            _ => "".to_string(),
+        };
+        format!("{}://{}{}{}", self.scheme, self.hostname, port, path)
+    }
 }
 
 pub struct GerritChange {
@@ -33,38 +81,50 @@ pub struct Gerrit {
     pub git_dir: String,
 }
 
+impl Default for Gerrit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Gerrit {
     pub fn new() -> Self {
         let git_dir = git::get_git_directories().unwrap();
         info!("Get git directory: {}", git_dir);
         Self {
-            git_dir: git_dir,
+            git_dir,
         }
     }
 }
 
-pub fn get_gerrit_change(args: Args) -> GerritChange {
+/// Change of the id if given, otherwise of the Change-Id of HEAD
+pub fn resolve_change(url: Option<&str>, remote: &str, change_id: Option<&str>) -> Result<GerritChange, String> {
+    let conn = get_remote_conn(url, remote)?;
+    let change_id = match change_id {
+        Some(id) => id.to_string(),
+        None => git::get_local_commit_change_id("HEAD").unwrap_or_default(),
+    };
+    if change_id.is_empty() {
+        return Err("No Change-Id in HEAD, give the change explicitly".to_string());
+    }
+    Ok(GerritChange { conn, change_id })
+}
 
-    let change = GerritChange {
-        // FIXME
-        conn: parse_gerrit_ssh_params_from_git_url(&args.url.unwrap_or("git@github.com:ponsheng/gerrit-reviewers.git".to_string())),
-        change_id: args.change.unwrap_or("NA".to_string()),
+/// Connection of the url if given, otherwise of the git remote
+pub fn get_remote_conn(url: Option<&str>, remote: &str) -> Result<GitUrl, String> {
+    let remote_url = match url {
+        Some(u) => u.to_string(),
+        None => git::get_remote_url(remote)?,
     };
-    change
+    parse_gerrit_ssh_params_from_git_url(&remote_url)
 }
 
-fn parse_gerrit_ssh_params_from_git_url(remote_url: &str) -> GitUrl {
+fn parse_gerrit_ssh_params_from_git_url(remote_url: &str) -> Result<GitUrl, String> {
     let mut ret;
 
-    if remote_url.find("://").is_some() {
-        let parsed_url;
-        let result = Url::parse(remote_url);
-        match result {
-            Ok(url) => parsed_url = url,
-            Err(e) => {
-                panic!("{}", e.to_string());
-            }
-        }
+    if remote_url.contains("://") {
+        let parsed_url = Url::parse(remote_url)
+            .map_err(|e| format!("Invalid url {}: {}", remote_url, e))?;
 
         ret = GitUrl {
             scheme: {
@@ -79,7 +139,7 @@ fn parse_gerrit_ssh_params_from_git_url(remote_url: &str) -> GitUrl {
             hostname: {
                 match parsed_url.host_str() {
                     Some(s) => s.to_string(),
-                    None => panic!("Hostname is not found")
+                    None => return Err(format!("No hostname in url {}", remote_url)),
                 }
             },
             port: parsed_url.port_or_known_default(),
@@ -104,49 +164,47 @@ fn parse_gerrit_ssh_params_from_git_url(remote_url: &str) -> GitUrl {
     let re = Regex::new(r"^/|(\.git$)").unwrap();
     ret.project = re.replace_all(&ret.project, "").to_string();
 
-    ret
+    Ok(ret)
 }
 
-fn query_reviews_over_ssh(remote_url: &str, more_args: &str) -> Vec<serde_json::Value> {
-    let url = parse_gerrit_ssh_params_from_git_url(&remote_url);
+/// One page of query results, and whether more changes match
+struct QueryPage {
+    changes: Vec<serde_json::Value>,
+    more: bool,
+}
 
-    let mut query;
-    let user_host;
+// Changes of all projects are queried if the project of url is empty
+fn query_reviews_over_ssh(url: &GitUrl, options: &[&str], more_query: &str, start: usize, limit: u32) -> Result<QueryPage, String> {
+    let mut query = Vec::new();
 
     // status:open
-    query = format!("project:{}", url.project);
-
-    match url.username {
-        Some(s) => {
-            user_host = format!("{}@{}", s, url.hostname);
-        },
-        None => user_host = format!("{}", url.hostname),
+    if !url.project.is_empty() {
+        query.push(format!("project:{}", ssh::quote_query_value(&url.project)));
+    }
+    if !more_query.is_empty() {
+        query.push(more_query.to_string());
     }
-
-    let port = {
-        match url.port {
-            Some(p) => format!("-p {}", p),
-            None => "".to_string()
-        }
-    };
 
     // Set limit
-    if QUERY_LIMIT > 0 {
-        query.push_str(&format!(" limit: {}", QUERY_LIMIT));
+    if limit > 0 {
+        query.push(format!("limit:{}", limit));
     }
 
-    let cmd = vec!["ssh", "-x", &*port, &*user_host, "gerrit", "query", "--format=JSON", more_args, &*query];
-    let resp = os::run_command_exc(cmd).unwrap();
-
-    /*
-        Ok(s) => ,
-        Err(s) => panic!("{}", s)
-    };*/
+    let mut cmd = SshCommand::new(url, "query")
+        .arg("--format=JSON")
+        .args(options);
+    if start > 0 {
+        cmd = cmd.arg("--start").arg(&start.to_string());
+    }
+    let resp = cmd.arg(&query.join(" ")).run()?;
 
     let mut changes = Vec::new();
-    for line in resp.split('\n') {
-        let json: serde_json::Value = serde_json::from_str(line).unwrap();
+    let mut more = false;
+    for line in resp.lines() {
+        let json: serde_json::Value = serde_json::from_str(line)
+            .map_err(|e| format!("Invalid query result: {}", e))?;
         if json.get("rowCount").is_some() {
+            more = json["moreChanges"].as_bool().unwrap_or(false);
             break;
         }
         changes.push(json);
@@ -155,118 +213,522 @@ fn query_reviews_over_ssh(remote_url: &str, more_args: &str) -> Vec<serde_json::
     debug!("Queried {} changes", changes.len());
     
     //debug!(output);
-    changes
+    Ok(QueryPage { changes, more })
 }
 
-pub fn query_reviews(remote_url: &str, more_args: &str) -> Vec<serde_json::Value> {
-    if remote_url.starts_with("http://") || remote_url.starts_with("https://") {
-        // TODO
-        panic!("HTTP not supported yet");
-    } else {
-        // ssh
-        query_reviews_over_ssh(remote_url, more_args)
+// Results are converted to the format of `gerrit query --format=JSON`
+fn query_reviews_over_rest(url: &GitUrl, options: &[&str], more_query: &str, start: usize, limit: u32) -> Result<QueryPage, String> {
+    let mut query = Vec::new();
+    if !url.project.is_empty() {
+        query.push(format!("project:{}", ssh::quote_query_value(&url.project)));
+    }
+    if !more_query.is_empty() {
+        query.push(more_query.to_string());
+    }
+
+    let q: String = form_urlencoded::byte_serialize(query.join(" ").as_bytes()).collect();
+    let mut path = format!("/changes/?q={}&n={}&o=DETAILED_ACCOUNTS", q, limit);
+    if start > 0 {
+        path.push_str(&format!("&S={}", start));
+    }
+    for o in options {
+        match *o {
+            "--all-reviewers" | "--all-approvals" => path.push_str("&o=DETAILED_LABELS"),
+            "--current-patch-set" => path.push_str("&o=CURRENT_REVISION"),
+            "--comments" => path.push_str("&o=MESSAGES"),
+            "--submit-records" => path.push_str("&o=SUBMIT_REQUIREMENTS"),
+            "--files" => path.push_str("&o=CURRENT_FILES"),
+            _ => debug!("Option {} is ignored over HTTP", o),
+        }
     }
+
+    let json = rest_get(url, &path)?;
+    let changes = match json.as_array() {
+        Some(c) => c,
+        None => return Err(format!("Unexpected result of query {}", more_query)),
+    };
+    // The last change tells if there are more
+    let more = changes.last().and_then(|c| c["_more_changes"].as_bool()).unwrap_or(false);
+    Ok(QueryPage {
+        changes: changes.iter().map(change_info_to_query_result).collect(),
+        more,
+    })
 }
 
-pub fn add_reviewer(change: &GerritChange, name: &str) -> Result<String, String> {
-    // TODO use trait for ssh/http
-    if change.conn.scheme.eq("ssh") {
-        let reviewers = vec![GerritUser::from_str(name)];
-        set_reviewers_over_ssh(change, &reviewers, true)
-    } else {
-        panic!("Unsupported scheme");
+// REST timestamps are UTC, e.g. "2024-05-02 09:30:00.000000000"
+fn rest_timestamp(value: &serde_json::Value) -> serde_json::Value {
+    value.as_str()
+        .and_then(|s| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f").ok())
+        .map(|t| json!(t.and_utc().timestamp()))
+        .unwrap_or(serde_json::Value::Null)
+}
+
+// Votes on patch sets before the current one are only told by the messages,
+// e.g. "Patch Set 2: Code-Review+1 Verified-1"
+fn older_approvals_from_messages(info: &serde_json::Value, current: u64) -> Vec<serde_json::Value> {
+    let header = Regex::new(r"^Patch Set (\d+):(.*)$").unwrap();
+    let vote = Regex::new(r"^([A-Za-z0-9-]+?)([+-]\d+)$").unwrap();
+
+    let mut patch_sets: Vec<(u64, Vec<serde_json::Value>)> = Vec::new();
+    for m in info["messages"].as_array().into_iter().flatten() {
+        let first_line = m["message"].as_str().unwrap_or_default().lines().next().unwrap_or_default();
+        let caps = match header.captures(first_line) {
+            Some(c) => c,
+            None => continue,
+        };
+        let number: u64 = caps[1].parse().unwrap_or(0);
+        if number == 0 || number >= current {
+            continue;
+        }
+        for token in caps[2].split_whitespace() {
+            if let Some(v) = vote.captures(token) {
+                let approval = json!({
+                    "type": &v[1],
+                    "value": v[2].trim_start_matches('+'),
+                    "grantedOn": rest_timestamp(&m["date"]),
+                    "by": m["author"],
+                });
+                match patch_sets.iter_mut().find(|(n, _)| *n == number) {
+                    Some((_, approvals)) => approvals.push(approval),
+                    None => patch_sets.push((number, vec![approval])),
+                }
+            }
+        }
     }
+    patch_sets.sort_by_key(|(n, _)| *n);
+    patch_sets.into_iter().map(|(n, approvals)| json!({ "number": n, "approvals": approvals })).collect()
 }
 
-pub fn delete_reviewers(change: &GerritChange, reviewers: &Vec<GerritUser>) -> Result<String, String> {
-    // TODO use trait for ssh/http
-    if change.conn.scheme.eq("ssh") {
-        set_reviewers_over_ssh(change, reviewers, false)
+fn change_info_to_query_result(info: &serde_json::Value) -> serde_json::Value {
+    let mut ret = json!({
+        "project": info["project"],
+        "branch": info["branch"],
+        "id": info["change_id"],
+        "number": info["_number"],
+        "subject": info["subject"],
+        "owner": info["owner"],
+        "open": info["status"] == "NEW",
+        "status": info["status"],
+        "createdOn": rest_timestamp(&info["created"]),
+        "lastUpdated": rest_timestamp(&info["updated"]),
+    });
+    if let Some(reviewers) = info["reviewers"].get("REVIEWER") {
+        ret["allReviewers"] = reviewers.clone();
+    }
+    if let Some(rev) = info["current_revision"].as_str() {
+        let revision = &info["revisions"][rev];
+        ret["currentPatchSet"] = json!({
+            "revision": rev,
+            "number": revision["_number"],
+            "uploader": revision["uploader"],
+        });
+        if let Some(files) = revision["files"].as_object() {
+            ret["currentPatchSet"]["files"] = files.keys().map(|f| json!({ "file": f })).collect();
+        }
+    }
+    if let Some(messages) = info["messages"].as_array() {
+        ret["comments"] = messages.iter().map(|m| json!({
+            "timestamp": rest_timestamp(&m["date"]),
+            "reviewer": m["author"],
+            "message": m["message"],
+            "patchSet": m["_revision_number"],
+        })).collect();
+    }
+    if let Some(requirements) = info.get("submit_requirements") {
+        ret["submitRequirements"] = requirements.clone();
+    }
+    if let Some(labels) = info["labels"].as_object() {
+        // Votes of all labels, as approvals of the current patch set
+        let mut approvals = Vec::new();
+        // Highest vote each reviewer may give, e.g. to tell who can approve
+        let mut ranges = Vec::new();
+        // Highest value of each label, what MAX of submit requirements means
+        let mut max = serde_json::Map::new();
+        for (label, detail) in labels {
+            for vote in detail["all"].as_array().into_iter().flatten() {
+                if let Some(m) = vote["permitted_voting_range"]["max"].as_i64() {
+                    ranges.push(json!({ "type": label, "max": m, "by": vote }));
+                }
+                let value = vote["value"].as_i64().unwrap_or(0);
+                if value == 0 {
+                    continue;
+                }
+                approvals.push(json!({
+                    "type": label,
+                    "value": value.to_string(),
+                    "grantedOn": rest_timestamp(&vote["date"]),
+                    "by": vote,
+                }));
+            }
+            let values = detail["values"].as_object().into_iter().flatten()
+                .filter_map(|(v, _)| v.trim().trim_start_matches('+').parse::<i64>().ok());
+            if let Some(m) = values.max() {
+                max.insert(label.clone(), json!(m));
+            }
+        }
+        ret["permittedVotes"] = json!(ranges);
+        ret["labelMax"] = serde_json::Value::Object(max);
+
+        // Labels only hold the current votes
+        if ret.get("currentPatchSet").is_none() {
+            ret["currentPatchSet"] = json!({});
+        }
+        ret["currentPatchSet"]["approvals"] = json!(approvals);
+        let current = ret["currentPatchSet"]["number"].as_u64();
+        let mut patch_sets = match current {
+            Some(n) => older_approvals_from_messages(info, n),
+            None => Vec::new(),
+        };
+        patch_sets.push(json!({ "number": current, "approvals": approvals }));
+        ret["patchSets"] = json!(patch_sets);
+    }
+    if let Some(attention) = info["attention_set"].as_object() {
+        ret["attentionSet"] = attention.values().map(|a| a["account"].clone()).collect();
+    }
+    ret
+}
+
+fn query_page(conn: &GitUrl, options: &[&str], query: &str, start: usize, limit: u32) -> Result<QueryPage, String> {
+    if conn.is_http() {
+        query_reviews_over_rest(conn, options, query, start, limit)
     } else {
-        panic!("Unsupported scheme");
+        // ssh
+        query_reviews_over_ssh(conn, options, query, start, limit)
     }
 }
 
-/// NOTE non-owner might not be able to remove reviewers
-fn set_reviewers_over_ssh(change: &GerritChange, reviewers: &Vec<GerritUser>, is_add: bool) -> Result<String, String> {
-    assert!(reviewers.len() > 0, "Reviewer list is empty");
-
-    let change_id = &change.change_id;
-    let url = &change.conn;
-    let user_host = {
-        match &url.username {
-            Some(s) => format!("{}@{}", s, url.hostname),
-            None => format!("{}", url.hostname),
-        }
-    };
+pub fn query_reviews(conn: &GitUrl, options: &[&str], query: &str) -> Result<Vec<serde_json::Value>, String> {
+    query_page(conn, options, query, 0, QUERY_LIMIT).map(|p| p.changes)
+}
 
-    let port = {
-        match url.port {
-            Some(p) => format!("-p {}", p),
-            None => "".to_string()
+/// All changes matching the query, fetched page by page, at most `max`
+pub fn query_all_reviews(conn: &GitUrl, options: &[&str], query: &str, max: usize) -> Result<Vec<serde_json::Value>, String> {
+    let mut changes = Vec::new();
+    while changes.len() < max {
+        let limit = (max - changes.len()).min(PAGE_SIZE as usize) as u32;
+        let page = query_page(conn, options, query, changes.len(), limit)?;
+        let done = !page.more || page.changes.is_empty();
+        changes.extend(page.changes);
+        if done {
+            return Ok(changes);
         }
+        debug!("Queried {} changes, more to come", changes.len());
+    }
+    info!("Stopped at {} changes", max);
+    Ok(changes)
+}
+
+/// Open changes of the topic in all projects, each with the connection
+/// to its own project
+pub fn get_topic_changes(conn: &GitUrl, topic: &str) -> Result<Vec<GerritChange>, String> {
+    let all_projects = conn.with_project("");
+    let query = format!("status:open topic:{}", ssh::quote_query_value(topic));
+
+    let mut changes = Vec::new();
+    for r in query_all_reviews(&all_projects, &[], &query, usize::MAX)? {
+        let project = r["project"].as_str().unwrap_or_default();
+        let number = match r["number"].as_u64() {
+            Some(n) => n.to_string(),
+            // Older Gerrit reports the number as string
+            None => r["number"].as_str().unwrap_or_default().to_string(),
+        };
+        changes.push(GerritChange {
+            conn: conn.with_project(project),
+            change_id: number,
+        });
+    }
+    Ok(changes)
+}
+
+// Gerrit prepends this to JSON responses to prevent XSSI
+const REST_MAGIC_PREFIX: &str = ")]}'";
+
+fn rest_get(conn: &GitUrl, path: &str) -> Result<serde_json::Value, String> {
+    let resp = rest_request(conn, path, &["-H", "Accept: application/json"], false)?;
+
+    let body = resp.trim_start().trim_start_matches(REST_MAGIC_PREFIX);
+    serde_json::from_str(body).map_err(|e| format!("Invalid response from {}: {}", conn.rest_url(path), e))
+}
+
+fn rest_post(conn: &GitUrl, path: &str, body: &serde_json::Value) -> Result<String, String> {
+    let data = body.to_string();
+    rest_request(conn, path, &["-X", "POST", "-H", "Content-Type: application/json", "--data", &*data], true)
+}
+
+// Authenticated requests go to the "/a/" prefixed endpoints. The credential is
+// passed to curl on stdin, anonymous access is tried when none is found.
+fn rest_request(conn: &GitUrl, path: &str, args: &[&str], mutation: bool) -> Result<String, String> {
+    let credential = auth::get_credential(&conn.scheme, &conn.hostname, conn.username.as_deref());
+    let url = match credential {
+        Some(_) => conn.rest_url(&format!("/a{}", path)),
+        None => conn.rest_url(path),
     };
-    let project_str = format!("-p {}", url.project);
 
-    let mut option_str = String::new();
+    let mut cmd = vec!["curl", "-s", "-S", "-f", "--config", "-"];
+    cmd.extend_from_slice(args);
+    cmd.push(&*url);
 
-    let cur_reviewers = get_reviewers(change);
+    if mutation && plan::is_dry_run() {
+        return Ok(format!("Would run: {}", cmd.join(" ")));
+    }
 
-    for user in reviewers {
-        let mut already_has_user = false;
-        for r in &cur_reviewers {
-            if user.username.eq(&r.username) {
-                already_has_user = true;
-            }
+    let input = credential.as_ref().map(|c| c.to_curl_config()).unwrap_or_default();
+    let network = config::get_network_settings();
+    let runner = CommandRunner::new(&cmd)
+        .input(&input)
+        .timeout(network.timeout)
+        .retries(network.retries, is_transient_curl_error);
+    runner.run().map_err(|e| {
+        if !e.contains("error: 401") && !e.contains("error: 403") {
+            return e;
+        }
+        match &credential {
+            Some(c) => format!("Authentication as {} rejected by {}", c, conn.hostname),
+            None => format!("Authentication required by {}, no credential found in: {}",
+                            conn.hostname, auth::describe_order()),
         }
+    })
+}
 
-        // Check if skip the user
-        if is_add && already_has_user {
-            println!("{} is already a reviewer", user.username);
-            continue;
-        } else if !is_add && !already_has_user {
-            println!("{} is not a reviewer", user.username);
+// Failures to resolve, connect or receive, and server errors
+fn is_transient_curl_error(err: &CommandError) -> bool {
+    match err.code() {
+        Some(6) | Some(7) | Some(28) | Some(35) | Some(52) | Some(56) => true,
+        Some(22) => err.stderr().contains("error: 50"),
+        _ => matches!(err, CommandError::Timeout(_)),
+    }
+}
+
+/// Query the members of a Gerrit internal group, including nested groups
+pub fn get_group_members(conn: &GitUrl, group: &str) -> Result<Vec<GerritUser>, String> {
+    if conn.is_ssh() {
+        get_group_members_over_ssh(conn, group)
+    } else if conn.is_http() {
+        let id: String = form_urlencoded::byte_serialize(group.as_bytes()).collect();
+        let json = rest_get(conn, &format!("/groups/{}/members/?recursive", id))?;
+        let members = match json.as_array() {
+            Some(m) => m,
+            None => return Err(format!("Unexpected members of group {}", group)),
+        };
+        Ok(members.iter().map(users::get_git_user).collect())
+    } else {
+        Err("Unsupported scheme".to_string())
+    }
+}
+
+fn get_group_members_over_ssh(url: &GitUrl, group: &str) -> Result<Vec<GerritUser>, String> {
+    let cmd = SshCommand::new(url, "ls-members").arg("--recursive").arg(group);
+    let resp = cmd.run()?;
+
+    // Tab separated table: id, username, full name, email
+    let mut members = Vec::new();
+    for line in resp.lines().skip(1) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 4 {
             continue;
         }
+        let field = |i: usize| {
+            if fields[i] == "n/a" { String::new() } else { fields[i].to_string() }
+        };
+        members.push(GerritUser {
+            username: field(1),
+            full_name: field(2),
+            email: field(3),
+            account_id: fields[0].parse().ok(),
+        });
+    }
+    debug!("Group {} has {} members", group, members.len());
+    Ok(members)
+}
 
-        if is_add {
-            option_str.push_str(" --add ");
-        } else {
-            option_str.push_str(" --remove ");
-        }
-        option_str.push_str(&user.username.to_string());
+/// Status message the user set on the Gerrit account, e.g. "On vacation"
+pub fn get_account_status(conn: &GitUrl, user: &GerritUser) -> Result<Option<String>, String> {
+    if !conn.is_http() {
+        // SSH commands do not expose the account status
+        return Err("Account status is only available over HTTP".to_string());
     }
+    let id: String = form_urlencoded::byte_serialize(user.account().as_bytes()).collect();
+    let json = rest_get(conn, &format!("/accounts/{}", id))?;
+    Ok(json["status"].as_str().map(|s| s.to_string()))
+}
+
+/// Number of open changes the user is reviewing across all projects,
+/// counted up to OPEN_REVIEWS_CAP
+pub fn count_open_reviews(conn: &GitUrl, user: &GerritUser) -> Result<usize, String> {
+    let query = format!("status:open reviewer:{}", ssh::quote_query_value(&user.account()));
+    let reviews = query_all_reviews(&conn.with_project(""), &[], &query, OPEN_REVIEWS_CAP)?;
+    Ok(reviews.len())
+}
 
-    if option_str.len() != 0 {
-        let cmd = vec!["ssh", "-x", &*port, &*user_host, "gerrit", "set-reviewers", &*project_str, &*option_str, change_id];
-        os::run_command_exc(cmd)
+pub fn add_reviewers(change: &GerritChange, reviewers: &[GerritUser]) -> Result<String, String> {
+    let mut plan = ReviewerPlan::new(change);
+    plan.adds = reviewers.to_vec();
+    apply_plan(&plan)
+}
+
+pub fn add_reviewer(change: &GerritChange, name: &str) -> Result<String, String> {
+    let reviewers = vec![GerritUser::from_str(name)];
+    add_reviewers(change, &reviewers)
+}
+
+pub fn delete_reviewers(change: &GerritChange, reviewers: &[GerritUser]) -> Result<String, String> {
+    let mut plan = ReviewerPlan::new(change);
+    plan.removes = reviewers.to_vec();
+    apply_plan(&plan)
+}
+
+/// Send the reviewer changes of the plan to Gerrit and record them in the
+/// journal. Returns what was done, or what would be done in dry-run mode,
+/// after notes on the users left out.
+pub fn apply_plan(plan: &ReviewerPlan) -> Result<String, String> {
+    apply_plan_with_journal(plan, true)
+}
+
+/// Like apply_plan, but not recorded in the journal, e.g. to undo a journal entry
+pub fn apply_plan_without_journal(plan: &ReviewerPlan) -> Result<String, String> {
+    apply_plan_with_journal(plan, false)
+}
+
+fn apply_plan_with_journal(plan: &ReviewerPlan, record: bool) -> Result<String, String> {
+    let cur_reviewers = get_reviewers(plan.change)?;
+    let (mut plan, mut notes) = plan.prune(&cur_reviewers);
+    if plan.change.conn.is_ssh() && !(plan.ccs.is_empty() && plan.cc_removes.is_empty()) {
+        let ccs: Vec<String> = plan.ccs.iter().chain(&plan.cc_removes).map(|u| u.to_string()).collect();
+        notes.push(format!("CC is not supported over SSH, skip: {}", ccs.join(", ")));
+        plan.ccs.clear();
+        plan.cc_removes.clear();
+    }
+    if plan.is_empty() {
+        notes.push("Nothing happened".to_string());
+        return Ok(notes.join("\n"));
+    }
+
+    // TODO use trait for ssh/http
+    let ret = if plan.change.conn.is_ssh() {
+        set_reviewers_over_ssh(&plan)
+    } else if plan.change.conn.is_http() {
+        set_reviewers_over_rest(&plan)
     } else {
-        Ok("Nothing happened".to_string())
+        Err("Unsupported scheme".to_string())
+    }?;
+
+    notes.push(plan.describe());
+    if plan::is_dry_run() {
+        // The command which would have been run
+        notes.push(ret);
+    } else if record {
+        // Gerrit expands the groups, read back who they added
+        let after = if plan.groups.is_empty() {
+            Ok(plan.apply_to(&cur_reviewers))
+        } else {
+            get_reviewers(plan.change)
+        };
+        // Reviewers made CCs are restored as reviewers, not removed on undo
+        let ccs: Vec<GerritUser> = plan.ccs.iter()
+            .filter(|u| !cur_reviewers.iter().any(|r| r.same_account(u)))
+            .cloned()
+            .collect();
+        if let Err(err) = after.and_then(|after| journal::record(plan.change, &cur_reviewers, &after, &ccs)) {
+            notes.push(format!("Failed to record journal: {}", err));
+        }
     }
+    Ok(notes.join("\n"))
 }
 
-/*
-pub fn is_change_open(change_id: &str) -> bool {
-    let remote_url = git::get_remote_url("...".to_string());
+/// NOTE non-owner might not be able to remove reviewers
+fn set_reviewers_over_ssh(plan: &ReviewerPlan) -> Result<String, String> {
+    let url = &plan.change.conn;
 
-    let more_args = format!("status:open {}", change_id);
-    let reviews = query_reviews(remote_url, &more_args);
+    let mut cmd = SshCommand::new(url, "set-reviewers").arg("-p").arg(&url.project);
+    for user in &plan.adds {
+        cmd = cmd.arg("--add").arg(&user.account());
+    }
+    for group in &plan.groups {
+        cmd = cmd.arg("--add").arg(group);
+    }
+    for user in &plan.removes {
+        cmd = cmd.arg("--remove").arg(&user.account());
+    }
+    cmd.arg(&plan.change.change_id).run_mutation()
+}
 
-    if reviews.len() == 0 {
-        return false;
+fn set_reviewers_over_rest(plan: &ReviewerPlan) -> Result<String, String> {
+    let mut reviewers = Vec::new();
+    for u in &plan.adds {
+        reviewers.push(json!({ "reviewer": u.account(), "state": "REVIEWER" }));
+    }
+    for g in &plan.groups {
+        reviewers.push(json!({ "reviewer": g, "state": "REVIEWER" }));
+    }
+    for u in &plan.ccs {
+        reviewers.push(json!({ "reviewer": u.account(), "state": "CC" }));
+    }
+    for u in plan.removes.iter().chain(&plan.cc_removes) {
+        reviewers.push(json!({ "reviewer": u.account(), "state": "REMOVED" }));
     }
+    let body = json!({ "reviewers": reviewers });
 
-    let obj = reviews.get(0).unwrap();
+    let id: String = form_urlencoded::byte_serialize(plan.change.change_id.as_bytes()).collect();
+    rest_post(&plan.change.conn, &format!("/changes/{}/revisions/current/review", id), &body)
+}
+
+pub fn is_change_open(change: &GerritChange) -> Result<bool, String> {
+    let conn = &change.conn;
+
+    let query = format!("status:open {}", change.change_id);
+    let reviews = query_reviews(conn, &[], &query)?;
 
-    obj.get("open").unwrap().as_bool().unwrap()
+    match reviews.first() {
+        Some(obj) => Ok(obj["open"].as_bool().unwrap_or(false)),
+        None => Ok(false),
+    }
 }
-*/
 
-pub fn get_reviewers(change: &GerritChange) -> Vec<GerritUser> {
-    let remote_url = &change.conn.raw;
-    let more_args = format!("--all-reviewers {}", change.change_id);
+/// Change-Ids of the open changes related to the change, from the bottom
+/// of the stack to the top
+pub fn get_related_change_ids(change: &GerritChange) -> Result<Vec<String>, String> {
+    if !change.conn.is_http() {
+        return Err("Related changes are only available over HTTP".to_string());
+    }
+    let json = rest_get(&change.conn, &format!("/changes/{}/revisions/current/related", change.change_id))?;
+
+    let mut ids = Vec::new();
+    if let Some(related) = json["changes"].as_array() {
+        // Gerrit lists the top of the stack first
+        for c in related.iter().rev() {
+            let status = c["status"].as_str().unwrap_or_default();
+            if status == "MERGED" || status == "ABANDONED" {
+                continue;
+            }
+            if let Some(id) = c["change_id"].as_str() {
+                ids.push(id.to_string());
+            }
+        }
+    }
+    if ids.is_empty() {
+        ids.push(change.change_id.clone());
+    }
+    Ok(ids)
+}
+
+/// Owner and uploader of the current patch set of the change
+pub fn get_change_owners(change: &GerritChange) -> Result<Vec<GerritUser>, String> {
+    let conn = &change.conn;
+    let mut owners = Vec::new();
+    for r in query_reviews(conn, &["--current-patch-set"], &change.change_id)? {
+        if r.get("owner").is_some() {
+            owners.push(users::get_git_user(&r["owner"]));
+        }
+        if r["currentPatchSet"].get("uploader").is_some() {
+            owners.push(users::get_git_user(&r["currentPatchSet"]["uploader"]));
+        }
+    }
+    Ok(owners)
+}
 
-    let reviews = query_reviews(remote_url, &more_args);
+pub fn get_reviewers(change: &GerritChange) -> Result<Vec<GerritUser>, String> {
+    let conn = &change.conn;
+    let reviews = query_reviews(conn, &["--all-reviewers"], &change.change_id)?;
 
     let mut reviewer_list = Vec::new();
 
@@ -280,42 +742,41 @@ pub fn get_reviewers(change: &GerritChange) -> Vec<GerritUser> {
             reviewer_list.push(user);
         }
     }
-    reviewer_list
+    Ok(reviewer_list)
 }
 
-pub fn get_user_recent_reviews(conn: &GitUrl, user: &GerritUser) -> Vec<Review> {
-    let remote_url = &conn.raw;
-    let more_args = format!(" owner:{}", user.username);
-    query_reviews(remote_url, &more_args)
+pub fn get_user_recent_reviews(conn: &GitUrl, user: &GerritUser) -> Result<Vec<Review>, String> {
+    let query = format!("owner:{}", ssh::quote_query_value(&user.account()));
+    query_reviews(conn, &[], &query)
 }
 
-pub fn get_user_recent_reviewers(conn: &GitUrl, user: &GerritUser) -> Vec<GerritUser> {
-    let remote_url = &conn.raw;
-    let more_args = format!("--all-reviewers owner:{}", user.username);
+pub fn get_user_recent_reviewers(conn: &GitUrl, user: &GerritUser) -> Result<Vec<GerritUser>, String> {
+    let counts = get_user_recent_reviewer_counts(conn, user)?;
+    Ok(counts.into_iter().map(|(r, _)| r).collect())
+}
 
-    let reviews = query_reviews(remote_url, &more_args);
+/// Recent reviewers of the user's changes with the number of changes they
+/// reviewed, in the order first seen
+pub fn get_user_recent_reviewer_counts(conn: &GitUrl, user: &GerritUser) -> Result<Vec<(GerritUser, usize)>, String> {
+    let query = format!("owner:{}", ssh::quote_query_value(&user.account()));
 
-    
-    // Collect reviewers into HashSet
-    let mut reviewer_set = LinkedHashSet::new();
+    let reviews = query_reviews(conn, &["--all-reviewers"], &query)?;
+
+    let mut reviewer_list: Vec<(GerritUser, usize)> = Vec::new();
     for r in reviews {
         if r.get("allReviewers").is_none() {
             continue;
         }
         let reviewers = r["allReviewers"].as_array().unwrap();
         for reviewer in reviewers {
-            reviewer_set.insert(users::get_git_user(reviewer));
+            let user = users::get_git_user(reviewer);
+            match reviewer_list.iter_mut().find(|(u, _)| *u == user) {
+                Some((_, count)) => *count += 1,
+                None => reviewer_list.push((user, 1)),
+            }
         }
     }
 
-    let mut reviewer_list = Vec::new();
-    loop {
-        let reviewer = match reviewer_set.pop_front() {
-            Some(r) => reviewer_list.push(r),
-            None => break,
-        };
-    }
-
-    reviewer_list
+    Ok(reviewer_list)
 }
 
@@ -1,19 +1,31 @@
 
 use regex::Regex;
-use log::{debug, warn};
+use log::{debug, info, warn};
 use std::path::Path;
 
 use crate::os;
+use crate::plan;
 //use crate::gerrit_if;
 //use crate::users::GerritUser;
 
-fn git_config_get_value(section : &str, option : &str) -> Result<String, String> {
+pub fn git_config_get_value(section : &str, option : &str) -> Result<String, String> {
     let name = format!("{}.{}", section, option);
     let cmd = vec!["git", "config", "--get", &*name];
 
     let result = os::run_command_exc(cmd);
 
-    result
+    result.map(|s| s.trim_end().to_string())
+}
+
+// Return empty if the option is not set
+pub fn git_config_get_all(section : &str, option : &str) -> Vec<String> {
+    let name = format!("{}.{}", section, option);
+    let cmd = vec!["git", "config", "--get-all", &*name];
+
+    match os::run_command_exc(cmd) {
+        Ok(s) => s.lines().map(|l| l.to_string()).collect(),
+        Err(_) => Vec::new(),
+    }
 }
 
 
@@ -22,26 +34,73 @@ fn alias_url(url : String, _rewrite_push : bool) -> String {
     url
 }
 
-// FIXME remote not respected
-pub fn get_remote_url(_remote : String) -> String {
-    let push_url;
-    let ret = git_config_get_value("remote.origin", "pushurl");
-    match ret {
-        Ok(s) => push_url = alias_url(s, false),
+pub fn get_remote_url(remote : &str) -> Result<String, String> {
+    let section = format!("remote.{}", remote);
+    let push_url = match git_config_get_value(&section, "pushurl") {
+        Ok(s) => alias_url(s, false),
+        Err(_) => match git_config_get_value(&section, "url") {
+            Ok(s) => alias_url(s, true),
+            Err(_) => return Err(format!("Failed to get url of remote {}", remote)),
+        },
+    };
+    debug!("Found {} Push URL: {}", remote, push_url);
+    Ok(push_url)
+}
 
-        Err(_) => {
-            let ret = git_config_get_value("remote.origin", "url");
-            match ret {
-                Ok(s) => push_url = alias_url(s, true),
-                Err(_) => panic!("Failed to get remote url")
-            }
+// Branch of the upstream of HEAD, e.g. "main" for "origin/main"
+pub fn get_upstream_branch(remote : &str) -> Option<String> {
+    let cmd = vec!["git", "rev-parse", "--abbrev-ref", "--symbolic-full-name", "@{upstream}"];
+    let upstream = os::run_command_exc(cmd).ok()?;
+    let prefix = format!("{}/", remote);
+    upstream.trim().strip_prefix(&prefix).map(|b| b.to_string())
+}
+
+/// Push HEAD for review to refs/for/<branch>, with reviewers and CCs set
+/// through the refspec, or through push options when `use_push_options`.
+/// Returns the command instead of running it in dry-run mode
+pub fn push_for_review(remote : &str, branch : &str, reviewers : &[String], ccs : &[String],
+                       use_push_options : bool) -> Result<String, String> {
+    let mut options: Vec<String> = reviewers.iter().map(|r| format!("r={}", r)).collect();
+    options.extend(ccs.iter().map(|c| format!("cc={}", c)));
+
+    let mut refspec = format!("HEAD:refs/for/{}", branch);
+    let mut cmd = vec!["git".to_string(), "push".to_string()];
+    if use_push_options {
+        for o in &options {
+            cmd.push("-o".to_string());
+            cmd.push(o.to_string());
         }
+    } else if !options.is_empty() {
+        refspec.push('%');
+        refspec.push_str(&options.join(","));
     }
-    debug!("Found origin Push URL: {}", push_url);
-    push_url
+    cmd.push(remote.to_string());
+    cmd.push(refspec);
+
+    if plan::is_dry_run() {
+        return Ok(format!("Would run: {}", cmd.join(" ")));
+    }
+    info!("Running: {}", cmd.join(" "));
+    os::run_command_inherit(cmd.iter().map(|s| s.as_str()).collect())?;
+    Ok(String::new())
 }
 
 
+/// Names of the remotes of the repository
+pub fn get_remotes() -> Vec<String> {
+    match os::run_command_exc(vec!["git", "remote"]) {
+        Ok(s) => s.lines().map(|l| l.trim().to_string()).filter(|l| !l.is_empty()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Contents of the file at the revision, e.g. "origin/main" and "src/OWNERS",
+/// None if it does not exist there
+pub fn show_file(rev: &str, path: &str) -> Option<String> {
+    let spec = format!("{}:{}", rev, path);
+    os::run_command_exc(vec!["git", "show", &spec]).ok()
+}
+
 pub fn get_git_directories() -> Option<String> {
     let ret = os::run_command_exc(vec!["git", "rev-parse", "--show-toplevel", "--git-dir"]);
     match ret {
@@ -58,67 +117,108 @@ pub fn get_git_directories() -> Option<String> {
     }
 }
 
-// Return "" if command failed
-fn get_local_commit_message(git_ref: &str) -> String {
-    let cmd = vec!["git", "show", "-s", "--format=medium", git_ref];
-    match os::run_command_exc(cmd) {
-        Ok(stdout) => stdout,
-        Err(stderr) => "".to_string(),
+/// Local commit which has a Change-Id
+pub struct LocalChange {
+    pub sha: String,
+    pub change_id: String,
+    pub subject: String,
+}
+
+/// Commits between upstream and HEAD which have a Change-Id, oldest first
+pub fn get_local_changes(upstream: &str) -> Result<Vec<LocalChange>, String> {
+    let range = format!("{}..HEAD", upstream);
+    let cmd = vec!["git", "log", "--reverse", "--format=%H %s", &*range];
+    let out = os::run_command_exc(cmd)?;
+
+    let mut changes = Vec::new();
+    for line in out.lines() {
+        let (sha, subject) = line.split_once(' ').unwrap_or((line, ""));
+        match get_local_commit_change_id(sha) {
+            Some(id) if !id.is_empty() => changes.push(LocalChange {
+                sha: sha.to_string(),
+                change_id: id,
+                subject: subject.to_string(),
+            }),
+            _ => warn!("No Change-Id in commit {}", sha),
+        }
     }
+    Ok(changes)
+}
+
+/// Change-Id and reviewers declared in the trailers of a commit message,
+/// e.g. `Reviewer: alice` or `Cc: @team`
+pub struct CommitTrailers {
+    pub change_id: String,
+    pub reviewers: Vec<String>,
+    pub ccs: Vec<String>,
 }
 
-/*
-// Max: 8
-fn get_unmerged_local_changes() -> Vec<String> {
-    info!("Searching for target commit");
-
-    let mut commits = Vec::new();
-
-    for backward_index in 0..8 {
-        let git_ref = format!("HEAD~{}", backward_index);
-        info!("Ref: {}", git_ref);
-        match get_local_commit_change_id(&git_ref) {
-            Some(id) => {
-                if id.len() == 0 {
-                    continue;
-                }
-                info!("    Change id:{}", id);
-                if gerrit_if::is_change_open(&id) {
-                    commits.push(id);
-                }
-            },
-            None => break,
+/// Trailers of the trailer block of a commit message, one per line with
+/// continuation lines unfolded as `git log --format=%(trailers:only,unfold)` prints them
+pub fn parse_trailers(block: &str) -> CommitTrailers {
+    let change_id_re = Regex::new(r"^\s*Change-Id: ([A-Za-z0-9]+)$").unwrap();
+    let reviewer_re = Regex::new(r"(?i)^\s*(Reviewer|Cc):\s*(.+?)\s*$").unwrap();
+    // "Full Name <email>" is added by its email
+    let email_re = Regex::new(r"<([^>]+)>").unwrap();
+
+    let mut ret = CommitTrailers {
+        change_id: "".to_string(),
+        reviewers: Vec::new(),
+        ccs: Vec::new(),
+    };
+    for line in block.lines() {
+        if let Some(caps) = change_id_re.captures(line) {
+            let id = caps.get(1).unwrap().as_str();
+            if !ret.change_id.is_empty() {
+                warn!("Multiple Change-Id in commit");
+                continue;
+            }
+            ret.change_id = id.to_string();
+        } else if let Some(caps) = reviewer_re.captures(line) {
+            let value = caps.get(2).unwrap().as_str();
+            let name = match email_re.captures(value) {
+                Some(email) => email.get(1).unwrap().as_str(),
+                None => value,
+            };
+            if caps.get(1).unwrap().as_str().eq_ignore_ascii_case("cc") {
+                ret.ccs.push(name.to_string());
+            } else {
+                ret.reviewers.push(name.to_string());
+            }
         }
     }
-    commits
+    ret
 }
-*/
 
 // Return None if failed to use git_ref
-// Return "" if ChangeID not found
-fn get_local_commit_change_id(git_ref: &str) -> Option<String> {
-    let re = Regex::new(r"^\s*Change-Id: ([A-Za-z0-9]+)$").unwrap();
+// Return "" as change_id if ChangeID not found
+pub fn get_local_commit_trailers(git_ref: &str) -> Option<CommitTrailers> {
+    // Only the trailer block, "Reviewer:" in the body is not a trailer
+    let cmd = vec!["git", "log", "-1", "--format=%(trailers:only,unfold)", git_ref];
+    match os::run_command_exc(cmd) {
+        Ok(block) => Some(parse_trailers(&block)),
+        Err(_) => None,
+    }
+}
 
-    let message = get_local_commit_message(git_ref);
+// Return None if failed to use git_ref
+// Return "" if ChangeID not found
+pub fn get_local_commit_change_id(git_ref: &str) -> Option<String> {
+    get_local_commit_trailers(git_ref).map(|t| t.change_id)
+}
 
-    if message.len() == 0 {
-        return None;
+/// Write reviewers into the trailers of the HEAD commit message
+pub fn amend_reviewer_trailers(reviewers: &[String], ccs: &[String]) -> Result<String, String> {
+    let mut cmd = vec!["git", "-c", "trailer.ifexists=addIfDifferent", "commit", "--amend", "--no-edit", "--allow-empty"];
+    let trailers: Vec<String> = reviewers.iter().map(|r| format!("Reviewer: {}", r))
+        .chain(ccs.iter().map(|c| format!("Cc: {}", c)))
+        .collect();
+    if trailers.is_empty() {
+        return Ok("Nothing happened".to_string());
     }
-
-    let mut ret = "".to_string();
-    for line in message.lines() {
-        match re.captures(line) {
-            Some(caps) => {
-                let id = caps.get(1).unwrap().as_str();
-                if ret.len() > 0 {
-                    warn!("Multiple Change-Id in commit");
-                    continue;
-                }
-                ret = id.to_string();
-            },
-            None => continue,
-        }
+    for t in &trailers {
+        cmd.push("--trailer");
+        cmd.push(t);
     }
-    Some(ret)
+    plan::run_mutation(cmd)
 }
-
@@ -1,13 +1,18 @@
 // TODO Store queried reviewers under local directories
-use rand::{distributions::Alphanumeric, Rng};
-use log::trace;
+use std::fmt;
+use log::debug;
 use std::hash::{Hash, Hasher};
 use linked_hash_set::LinkedHashSet;
+use regex::Regex;
 
-//use crate::gerrit_if;
-use crate::users::{GerritUser, UsersTy};
+use crate::gerrit_if;
+use crate::gerrit_if::{GerritChange, GitUrl};
+use crate::users::{GerritUser, UsersTy, UserIdentifier, insert_user};
 use crate::config;
-use crate::config::{};
+use crate::config::{ConfigScope, GroupsConfig};
+use crate::selection::SelectMode;
+use crate::availability::Availability;
+use crate::candidates::UserRow;
 
 pub type UserGroups = LinkedHashSet<GerritUserGroup>;
 
@@ -15,6 +20,119 @@ pub type UserGroups = LinkedHashSet<GerritUserGroup>;
 pub struct GerritUserGroup {
     pub users: UsersTy,
     pub name: String,
+    /// Other groups whose members belong to this group
+    pub includes: LinkedHashSet<GroupRef>,
+    /// How reviewers are chosen when the group is added
+    pub mode: SelectMode,
+    /// Number of reviewers chosen by modes other than `all`
+    pub pick: usize,
+}
+
+/// Reference to a group, written as `@name` for a local group or
+/// `gerrit:name` for a Gerrit internal group
+#[derive(Eq, PartialEq, Hash, Clone, Debug)]
+pub enum GroupRef {
+    Local(String),
+    Gerrit(String),
+}
+
+const LOCAL_GROUP_PREFIX: &str = "@";
+const GERRIT_GROUP_PREFIX: &str = "gerrit:";
+
+impl GroupRef {
+    pub fn parse(s: &str) -> Option<GroupRef> {
+        if let Some(name) = s.strip_prefix(LOCAL_GROUP_PREFIX) {
+            return Some(GroupRef::Local(name.to_string()));
+        }
+        s.strip_prefix(GERRIT_GROUP_PREFIX)
+            .map(|name| GroupRef::Gerrit(name.to_string()))
+    }
+}
+
+impl fmt::Display for GroupRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GroupRef::Local(name) => write!(f, "{}{}", LOCAL_GROUP_PREFIX, name),
+            GroupRef::Gerrit(name) => write!(f, "{}{}", GERRIT_GROUP_PREFIX, name),
+        }
+    }
+}
+
+pub enum GroupMember {
+    User(GerritUser),
+    Group(GroupRef),
+}
+
+impl GroupMember {
+    pub fn parse(s: &str) -> Result<GroupMember, String> {
+        if let Some(r) = GroupRef::parse(s) {
+            return Ok(GroupMember::Group(r));
+        }
+        match UserIdentifier::parse(s) {
+            Some(id) => Ok(GroupMember::User(id.to_user())),
+            None => Err(format!("Invalid member: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for GroupMember {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GroupMember::User(u) => write!(f, "{}", u),
+            GroupMember::Group(r) => write!(f, "{}", r),
+        }
+    }
+}
+
+impl GerritUserGroup {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            users: LinkedHashSet::new(),
+            includes: LinkedHashSet::new(),
+            mode: SelectMode::All,
+            pick: 1,
+        }
+    }
+
+    /// Options written as `key = value` lines in the group section
+    pub fn options(&self) -> Vec<(&str, String)> {
+        let mut options = Vec::new();
+        if self.mode != SelectMode::All {
+            options.push(("mode", self.mode.name()));
+            options.push(("pick", self.pick.to_string()));
+        }
+        options
+    }
+
+    pub fn set_option(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            "mode" => match SelectMode::parse(value) {
+                Some(mode) => self.mode = mode,
+                None => return false,
+            },
+            "pick" => match value.parse::<usize>() {
+                Ok(n) if n > 0 => self.pick = n,
+                _ => return false,
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn insert_member(&mut self, member: GroupMember) -> bool {
+        match member {
+            GroupMember::User(u) => self.users.insert_if_absent(u),
+            GroupMember::Group(r) => self.includes.insert_if_absent(r),
+        }
+    }
+
+    pub fn remove_member(&mut self, member: &GroupMember) -> bool {
+        match member {
+            GroupMember::User(u) => self.users.remove(u),
+            GroupMember::Group(r) => self.includes.remove(r),
+        }
+    }
 }
 
 impl Hash for GerritUserGroup {
@@ -48,52 +166,138 @@ impl<'a> IntoIterator for &'a GerritUserGroup {
 }
 */
 
+// Must match the section header parsed by GroupsConfig::load
+pub fn is_valid_group_name(name: &str) -> bool {
+    let matcher = Regex::new(r"^[[:word:]]+$").unwrap();
+    matcher.is_match(name)
+}
 
-fn get_rand_string(char_num: usize) -> String {
-    rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(char_num)
-        .map(char::from)
-        .collect()
+pub fn to_members(names: &[String]) -> Result<Vec<GroupMember>, String> {
+    names.iter().map(|n| GroupMember::parse(n)).collect()
 }
 
-pub fn gen_rand_groups() ->  UserGroups {
-    let group_num = rand::random::<u8>() % 4 + 1;
+/// Users of an expanded group, and the Gerrit groups left for Gerrit to
+/// expand when they are added as reviewers
+#[derive(Default, Debug)]
+pub struct ExpandedGroup {
+    pub users: UsersTy,
+    pub gerrit_groups: Vec<String>,
+}
 
-    let mut groups = LinkedHashSet::new();
-    for i in 0..group_num {
-        let user_num = rand::random::<u8>() % 4 + 1;
+/// Expand a group into its users, following `@group` references recursively.
+/// Gerrit groups are queried from the server when `live` is set, otherwise they
+/// are kept by name so Gerrit expands them when they are added as reviewers.
+pub struct GroupExpander<'a> {
+    configs: Vec<GroupsConfig>,
+    conn: &'a GitUrl,
+    live: bool,
+}
 
-        let mut users = LinkedHashSet::new();
-        for i in 0..user_num {
-            let username = get_rand_string(5);
-            let u = GerritUser::from_string(username);
-            users.insert(u);
+impl<'a> GroupExpander<'a> {
+    pub fn new(conn: &'a GitUrl, live: bool) -> Self {
+        Self {
+            configs: config::get_group_configs(),
+            conn,
+            live,
         }
+    }
+
+    // Local groups shadow global groups of the same name
+    pub fn find(&self, name: &str) -> Option<&GerritUserGroup> {
+        self.configs.iter().find_map(|c| c.get(name))
+    }
 
-        let groupname = get_rand_string(2);
-        let g = GerritUserGroup {
-            users: users,
-            name: groupname,
+    pub fn expand(&self, name: &str) -> Result<ExpandedGroup, String> {
+        let mut stack = Vec::new();
+        let mut expanded = ExpandedGroup::default();
+        self.expand_into(name, &mut stack, &mut expanded)?;
+        Ok(expanded)
+    }
+
+    fn expand_into(&self, name: &str, stack: &mut Vec<String>, expanded: &mut ExpandedGroup) -> Result<(), String> {
+        if stack.iter().any(|n| n == name) {
+            return Err(format!("Group cycle detected: {} -> {}", stack.join(" -> "), name));
+        }
+        let group = match self.find(name) {
+            Some(g) => g,
+            None => return Err(format!("Group {} not found", name)),
         };
-        groups.insert(g);
+
+        stack.push(name.to_string());
+        for u in &group.users {
+            insert_user(&mut expanded.users, u.clone());
+        }
+        for r in &group.includes {
+            match r {
+                GroupRef::Local(n) => self.expand_into(n, stack, expanded)?,
+                GroupRef::Gerrit(n) if self.live => {
+                    for u in gerrit_if::get_group_members(self.conn, n)? {
+                        insert_user(&mut expanded.users, u);
+                    }
+                },
+                GroupRef::Gerrit(n) => {
+                    debug!("Gerrit group {} is not expanded", n);
+                    if !expanded.gerrit_groups.contains(n) {
+                        expanded.gerrit_groups.push(n.clone());
+                    }
+                },
+            }
+        }
+        stack.pop();
+        Ok(())
     }
+}
 
-    groups
+/// Expanded members of the group, those unavailable flagged with the reason,
+/// and the Gerrit groups which are not expanded
+pub fn expand_members(conn: &GitUrl, name: &str, live: bool) -> Result<(Vec<UserRow>, Vec<String>), String> {
+    let expander = GroupExpander::new(conn, live);
+    let availability = Availability::new(conn);
+    let expanded = expander.expand(name)?;
+    let rows = expanded.users.into_iter().map(|u| {
+        match availability.check(&u) {
+            Some(reason) => {
+                let mut row = UserRow::new(u, "UNAVAILABLE");
+                row.reasons.push(reason);
+                row
+            },
+            None => UserRow::new(u, "MEMBER"),
+        }
+    }).collect();
+    Ok((rows, expanded.gerrit_groups))
 }
 
-enum ConfigLevel {
-    Local,
-    Global,
+pub fn copy_group(name: &str, to: ConfigScope) -> Result<String, String> {
+    let src = config::get_group_config(to.other());
+    let group = match src.get(name) {
+        Some(g) => g.clone(),
+        None => return Err(format!("Group {} not found in {}", name, src.file_path)),
+    };
+
+    let mut dst = config::get_group_config(to);
+    dst.insert_group(group)?;
+    save(&dst)
 }
 
-pub fn write_config(gs: UserGroups) -> Result<String, String> {
+pub fn import_group(name: &str, change: &GerritChange, scope: ConfigScope) -> Result<String, String> {
+    let reviewers = gerrit_if::get_reviewers(change)?;
+    if reviewers.is_empty() {
+        return Err(format!("No reviewers on {}", change.change_id));
+    }
 
-    trace!("write_config");
-    let mut cfg = config::GroupsConfig::new(config::ConfigScope::Local);
-    cfg.clear();
-    cfg.set(&gs);
-    cfg.save();
+    let mut group = GerritUserGroup::new(name);
+    for r in reviewers {
+        group.insert_member(GroupMember::User(r));
+    }
 
-    Ok(String::new())
+    let mut cfg = config::get_group_config(scope);
+    cfg.insert_group(group)?;
+    save(&cfg)
+}
+
+pub fn save(cfg: &GroupsConfig) -> Result<String, String> {
+    match cfg.save() {
+        Ok(_) => Ok(format!("Saved {}", cfg.file_path)),
+        Err(e) => Err(format!("Failed to save {}: {}", cfg.file_path, e)),
+    }
 }
@@ -0,0 +1,81 @@
+use chrono::Local;
+use serde_json::Value;
+
+use crate::gerrit_if;
+use crate::gerrit_if::{GerritChange, GitUrl};
+use crate::ssh;
+use crate::users;
+use crate::users::GerritUser;
+
+use serde_json::Value as Review;
+
+// Enough for anyone's inbox, more are left out
+const MAX_CHANGES: usize = 200;
+
+/// Open change of someone else waiting for the user
+pub struct InboxEntry {
+    pub change: GerritChange,
+    pub number: String,
+    pub subject: String,
+    pub branch: String,
+    pub owner: GerritUser,
+    /// Seconds since the change was created
+    pub age: Option<i64>,
+    /// Votes of the user on the current patch set, e.g. "Code-Review+1"
+    pub my_votes: Vec<String>,
+    /// In the attention set, i.e. the owner waits for the user
+    pub attention: bool,
+}
+
+fn format_vote(approval: &Value) -> Option<String> {
+    let value = approval["value"].as_str().and_then(|v| v.parse::<i64>().ok())?;
+    if value == 0 {
+        return None;
+    }
+    Some(format!("{}{:+}", approval["type"].as_str().unwrap_or_default(), value))
+}
+
+fn to_entry(conn: &GitUrl, review: &Review, me: &GerritUser, now: i64) -> InboxEntry {
+    let number = match review["number"].as_u64() {
+        Some(n) => n.to_string(),
+        // Older Gerrit reports the number as string
+        None => review["number"].as_str().unwrap_or_default().to_string(),
+    };
+    let project = review["project"].as_str().unwrap_or_default();
+    let my_votes = review["currentPatchSet"]["approvals"].as_array().into_iter().flatten()
+        .filter(|a| users::get_git_user(&a["by"]).same_account(me))
+        .filter_map(format_vote)
+        .collect();
+    let is_me = |v: &Value| users::get_git_user(v).same_account(me);
+    let reviewer = review["allReviewers"].as_array().is_some_and(|rs| rs.iter().any(is_me));
+    // Without the attention set, e.g. over ssh, the change matched by attention only if not a reviewer
+    let attention = match review["attentionSet"].as_array() {
+        Some(accounts) => accounts.iter().any(is_me),
+        None => !reviewer,
+    };
+    InboxEntry {
+        change: GerritChange { conn: conn.with_project(project), change_id: number.clone() },
+        number,
+        subject: review["subject"].as_str().unwrap_or_default().to_string(),
+        branch: review["branch"].as_str().unwrap_or_default().to_string(),
+        owner: users::get_git_user(&review["owner"]),
+        age: review["createdOn"].as_i64().map(|c| (now - c).max(0)),
+        my_votes,
+        attention,
+    }
+}
+
+/// Open changes of all projects where the user is a reviewer or in the
+/// attention set, oldest first
+pub fn get_inbox(conn: &GitUrl, me: &GerritUser) -> Result<Vec<InboxEntry>, String> {
+    let all_projects = conn.with_project("");
+    let account = ssh::quote_query_value(&me.account());
+    let query = format!("status:open (reviewer:{0} OR attention:{0}) -owner:{0}", account);
+    let reviews = gerrit_if::query_all_reviews(&all_projects, &["--current-patch-set", "--all-reviewers"],
+                                               &query, MAX_CHANGES)?;
+
+    let now = Local::now().timestamp();
+    let mut entries: Vec<InboxEntry> = reviews.iter().map(|r| to_entry(conn, r, me, now)).collect();
+    entries.sort_by_key(|e| std::cmp::Reverse(e.age));
+    Ok(entries)
+}
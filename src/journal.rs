@@ -0,0 +1,190 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::{trace, warn};
+use chrono::{Local, TimeZone};
+use serde_json::{json, Value};
+
+use crate::config;
+use crate::gerrit_if;
+use crate::gerrit_if::GerritChange;
+use crate::plan;
+use crate::plan::ReviewerPlan;
+use crate::users::{GerritUser, UserIdentifier};
+
+// One JSON object per line, oldest first
+const JOURNAL_NAME: &str = "gerreviewer-journal.jsonl";
+
+/// Reviewer change made on a Gerrit change
+pub struct JournalEntry {
+    pub time: u64,
+    pub remote: String,
+    pub project: String,
+    pub change: String,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+    /// CCs added, removed again on undo
+    pub ccs: Vec<String>,
+    pub undone: bool,
+}
+
+impl JournalEntry {
+    fn to_json(&self) -> Value {
+        json!({
+            "time": self.time,
+            "remote": self.remote,
+            "project": self.project,
+            "change": self.change,
+            "before": self.before,
+            "after": self.after,
+            "ccs": self.ccs,
+            "undone": self.undone,
+        })
+    }
+
+    fn from_json(json: &Value) -> Option<JournalEntry> {
+        let names = |v: &Value| -> Option<Vec<String>> {
+            v.as_array()?.iter().map(|n| n.as_str().map(|s| s.to_string())).collect()
+        };
+        Some(JournalEntry {
+            time: json["time"].as_u64()?,
+            remote: json["remote"].as_str()?.to_string(),
+            project: json["project"].as_str()?.to_string(),
+            change: json["change"].as_str()?.to_string(),
+            before: names(&json["before"])?,
+            after: names(&json["after"])?,
+            // Entries recorded before CCs were journaled
+            ccs: names(&json["ccs"]).unwrap_or_default(),
+            undone: json["undone"].as_bool().unwrap_or(false),
+        })
+    }
+
+    pub fn added(&self) -> Vec<String> {
+        self.after.iter().filter(|n| !self.before.contains(n)).cloned().collect()
+    }
+
+    pub fn removed(&self) -> Vec<String> {
+        self.before.iter().filter(|n| !self.after.contains(n)).cloned().collect()
+    }
+
+    pub fn describe(&self) -> String {
+        let time = match Local.timestamp_opt(self.time as i64, 0).single() {
+            Some(t) => t.format("%Y-%m-%d %H:%M:%S").to_string(),
+            None => self.time.to_string(),
+        };
+        let mut s = format!("{} {} ({})", time, self.change, self.project);
+        for n in self.added() {
+            s.push_str(&format!(" +{}", n));
+        }
+        for n in self.removed() {
+            s.push_str(&format!(" -{}", n));
+        }
+        for n in &self.ccs {
+            s.push_str(&format!(" +cc:{}", n));
+        }
+        if self.undone {
+            s.push_str(" [undone]");
+        }
+        s
+    }
+}
+
+fn journal_path() -> Result<String, String> {
+    config::get_data_path(JOURNAL_NAME).ok_or("Not in a git repository".to_string())
+}
+
+fn usernames(users: &[GerritUser]) -> Vec<String> {
+    users.iter().map(|u| u.account()).collect()
+}
+
+// Names are what account() returned: a username, an account id or an email
+fn to_user(name: &str) -> GerritUser {
+    match UserIdentifier::parse(name) {
+        Some(id) => id.to_user(),
+        None => GerritUser::from_str(name),
+    }
+}
+
+pub fn record(change: &GerritChange, before: &[GerritUser], after: &[GerritUser], ccs: &[GerritUser]) -> Result<(), String> {
+    let entry = JournalEntry {
+        time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        remote: change.conn.raw.clone(),
+        project: change.conn.project().to_string(),
+        change: change.change_id.clone(),
+        before: usernames(before),
+        after: usernames(after),
+        ccs: usernames(ccs),
+        undone: false,
+    };
+
+    let path = journal_path()?;
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)
+        .map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    writeln!(file, "{}", entry.to_json()).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    trace!("Recorded {}", entry.describe());
+    Ok(())
+}
+
+pub fn load() -> Vec<JournalEntry> {
+    let path = match journal_path() {
+        Ok(p) if Path::new(&p).exists() => p,
+        _ => return Vec::new(),
+    };
+
+    let mut entries = Vec::new();
+    for line in fs::read_to_string(&path).unwrap_or_default().lines() {
+        let entry = serde_json::from_str::<Value>(line).ok()
+            .and_then(|json| JournalEntry::from_json(&json));
+        match entry {
+            Some(e) => entries.push(e),
+            None => warn!("Ignore invalid journal entry: {}", line),
+        }
+    }
+    entries
+}
+
+fn save(entries: &[JournalEntry]) -> Result<(), String> {
+    let path = journal_path()?;
+    let mut s = String::new();
+    for e in entries {
+        s.push_str(&e.to_json().to_string());
+        s.push('\n');
+    }
+    let mut file = File::create(&path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    file.write_all(s.as_bytes()).map_err(|e| format!("Failed to write {}: {}", path, e))
+}
+
+/// The entry undo() reverts: the latest one not undone yet
+pub fn next_undo() -> Option<JournalEntry> {
+    load().into_iter().rev().find(|e| !e.undone)
+}
+
+/// Revert the latest reviewer change which is not undone yet
+pub fn undo() -> Result<String, String> {
+    let mut entries = load();
+    let entry = match entries.iter_mut().rev().find(|e| !e.undone) {
+        Some(e) => e,
+        None => return Err("Nothing to undo".to_string()),
+    };
+
+    let conn = gerrit_if::get_remote_conn(Some(&entry.remote), "")?;
+    let change = GerritChange {
+        conn: conn.with_project(&entry.project),
+        change_id: entry.change.clone(),
+    };
+    let mut plan = ReviewerPlan::new(&change);
+    plan.adds = entry.removed().iter().map(|n| to_user(n)).collect();
+    plan.removes = entry.added().iter().map(|n| to_user(n)).collect();
+    plan.cc_removes = entry.ccs.iter().map(|n| to_user(n)).collect();
+
+    let undo = format!("Undo: {}", entry.describe());
+    let msg = gerrit_if::apply_plan_without_journal(&plan)?;
+    if plan::is_dry_run() {
+        return Ok(format!("{}\n{}", undo, msg));
+    }
+
+    entry.undone = true;
+    save(&entries)?;
+    Ok(format!("{}\n{}\nRestored reviewers of {}", undo, msg, change.change_id))
+}
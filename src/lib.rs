@@ -0,0 +1,49 @@
+//! Reviewer assignment for Gerrit changes, used by the `git-reviewers` command
+//! and embeddable by other tools.
+//!
+//! - Connection: [`connect`] a remote, [`GitUrl`]
+//! - Change resolution: [`resolve_change`], [`GerritChange`]
+//! - Reviewer operations: [`get_reviewers`], [`add_reviewers`], [`delete_reviewers`],
+//!   [`apply_plan`] with a [`ReviewerPlan`]
+//! - Groups: [`GroupsConfig`], [`GroupExpander`], [`pick_from_group`] with a [`GroupPick`]
+//! - Suggestions: [`suggest`], [`CandidateFilter`]
+//!
+//! Functions return data or messages and never print, the command line
+//! interface and its output formats belong to the binary. Everything talks to
+//! Gerrit through `ssh`, `curl` and `git`, which can be replaced with
+//! [`os::set_command_handler`].
+
+pub mod gerrit_if;
+pub mod git;
+pub mod os;
+pub mod groups;
+pub mod users;
+pub mod config;
+pub mod selection;
+pub mod availability;
+pub mod candidates;
+pub mod push;
+pub mod trailers;
+pub mod stack;
+pub mod topic;
+pub mod plan;
+pub mod journal;
+pub mod pool;
+pub mod stats;
+pub mod inbox;
+pub mod votes;
+pub mod requirements;
+mod ssh;
+mod auth;
+#[cfg(test)]
+mod tests;
+
+pub use gerrit_if::{GitUrl, GerritChange};
+pub use gerrit_if::get_remote_conn as connect;
+pub use gerrit_if::{resolve_change, get_reviewers, add_reviewers, delete_reviewers, apply_plan};
+pub use users::GerritUser;
+pub use plan::ReviewerPlan;
+pub use config::{ConfigScope, GroupsConfig};
+pub use groups::{GerritUserGroup, GroupExpander, GroupMember, GroupRef};
+pub use selection::{SelectMode, AssignmentHistory, GroupPick, pick_from_group};
+pub use candidates::{CandidateFilter, Skipped, UserRow, suggest};
@@ -1,88 +1,272 @@
-//use std::str;
+use git_reviewers::{gerrit_if, os, plan, candidates, config, groups};
+use git_reviewers::{push, stack, topic, journal, trailers, stats, inbox, requirements, votes};
+use git_reviewers::gerrit_if::GerritChange;
+use git_reviewers::config::ConfigScope;
+use git_reviewers::groups::GerritUserGroup;
+use git_reviewers::users::GerritUser;
 
-//use log::{debug};
-//use regex::Regex;
-
-mod tex_ui;
 mod args;
-mod gerrit_if;
-mod git;
-mod os;
-mod groups;
-mod users;
-mod config;
+mod report;
+mod tex_ui;
+
+use args::{GroupAction, TopicAction};
+
+fn _main() -> i32 {
+
+    let arg = args::parse();
+    env_logger::Builder::new()
+        .filter_level(arg.verbose.log_level_filter())
+        .init();
+    os::install_interrupt_handler();
+    plan::set_dry_run(arg.dry_run);
+    report::set_format(arg.format);
 
-// RUST_LOG=debug,info,warn
+    if let Some(command) = &arg.command {
+        return run_command(command, &arg);
+    }
 
-fn usage() {
-    println!("Hello, git-reviewer!");
+    tex_ui::TexUI.start(&arg);
+
+    0
 }
 
-/*
-fn list_reviews() {
-    let remote_url = git::get_remote_url("...".to_string());
-    debug!("remote url: {}", remote_url);
-
-    let reviews = gerrit_if::query_reviews(remote_url, "");
-    for r in reviews {
-        let project = r["project"].as_str().unwrap();
-        let branch = r["branch"].as_str().unwrap();
-        let subject = r["subject"].as_str().unwrap();
-        let owner = git::get_git_user(&r["owner"]);
-        println!("{}/{} | {} | {}", project, branch, owner.username, subject);
+fn get_gerrit_change(args: &args::Args) -> GerritChange {
+    GerritChange {
+        // FIXME
+        conn: gerrit_if::get_remote_conn(Some(args.url.as_deref().unwrap_or("git@github.com:ponsheng/gerrit-reviewers.git")), "")
+            .unwrap_or_else(|e| panic!("{}", e)),
+        change_id: args.change.clone().unwrap_or("NA".to_string()),
     }
 }
-*/
 
-// project, group, recent reviews
-fn _get_candidate_reviewers() {
+fn run_group_action(action: GroupAction, scope: ConfigScope, change: &GerritChange) -> Result<String, String> {
+    let mut cfg = config::get_group_config(scope);
+    let mut notes = Vec::new();
+    match action {
+        GroupAction::List => {
+            report::print_groups();
+            return Ok(String::new());
+        },
+        GroupAction::Create { name, members } => {
+            let mut group = GerritUserGroup::new(&name);
+            for m in groups::to_members(&members)? {
+                group.insert_member(m);
+            }
+            cfg.insert_group(group)?;
+        },
+        GroupAction::Rename { name, new_name } => cfg.rename_group(&name, &new_name)?,
+        GroupAction::Delete { name } => cfg.delete_group(&name)?,
+        GroupAction::Add { name, members } => cfg.add_members(&name, groups::to_members(&members)?)?,
+        GroupAction::Remove { name, members } => notes = cfg.remove_members(&name, &groups::to_members(&members)?)?,
+        GroupAction::Mode { name, mode, count } => cfg.set_mode(&name, mode, count)?,
+        GroupAction::Expand { name, live } => {
+            let (rows, gerrit_groups) = groups::expand_members(&change.conn, &name, live)?;
+            report::print_users(&rows);
+            let notes: Vec<String> = gerrit_groups.iter()
+                .map(|g| format!("Gerrit group {} is expanded by Gerrit, see --live", g))
+                .collect();
+            return Ok(notes.join("\n"));
+        },
+        GroupAction::Copy { name, to } => return groups::copy_group(&name, to),
+        GroupAction::Import { name } => return groups::import_group(&name, change, scope),
+    }
+    notes.push(groups::save(&cfg)?);
+    Ok(notes.join("\n"))
+}
 
+// Each result with the messages of the change indented under it
+fn print_result(label: &str, result: &Result<String, String>) {
+    match result {
+        Ok(msg) => {
+            println!("  {}: done", label);
+            for line in msg.lines() {
+                println!("    {}", line);
+            }
+        },
+        Err(err) => println!("  {}: failed: {}", label, err.trim()),
+    }
 }
 
-/*
-fn list_reviewers() {
-    /*
-    for r in get_reviewers(None) {
-        println!("{}", r.username);
+fn run_stack(req: &stack::StackRequest, arg: &args::Args) -> Result<String, String> {
+    let outcome = stack::run(req, arg.url.as_deref(), arg.change.as_deref())?;
+    for note in &outcome.notes {
+        println!("{}", note);
+    }
+    if !outcome.results.is_empty() {
+        println!("Results:");
+        for (pos, (c, result)) in outcome.stack.iter().zip(&outcome.results).enumerate() {
+            print_result(&format!("{}: {}", pos + 1, c.change.change_id), result);
+        }
     }
-    */
-    println!("Current reviewers:");
-    let cur_reviewers = gerrit_if::get_reviewers(Some("I1db9608c85fe80b30ffa881b60968d9695a463ff"));
-    for r in cur_reviewers {
-        println!("    |{}", r.username);
+    report::print_stack(&outcome.stack, &outcome.reviewers);
+    Ok(String::new())
+}
+
+/// Run the action on every open change of the topic
+fn run_topic(topic: &str, action: &TopicAction, url: Option<&str>, remote: &str) -> Result<String, String> {
+    let conn = gerrit_if::get_remote_conn(url, remote)?;
+    let changes = gerrit_if::get_topic_changes(&conn, topic)?;
+    if changes.is_empty() {
+        return Err(format!("No open changes in topic {}", topic));
     }
 
-    let _candicates = gerrit_if::get_reviewers(None);
-}*/
+    if let TopicAction::List = action {
+        let mut rows = Vec::new();
+        for c in &changes {
+            rows.push((c, topic::reviewer_rows(c)?));
+        }
+        report::print_change_users(&rows);
+        return Ok(String::new());
+    }
 
-fn _main() -> i32 {
+    let mut plans = Vec::new();
+    if let TopicAction::Clear = action {
+        plans = topic::clear_plans(&changes)?;
+        if plans.iter().all(|p| p.is_empty()) {
+            return Ok("No reviewers now".to_string());
+        }
+        for p in plans.iter().filter(|p| !p.is_empty()) {
+            println!("{}", p.describe());
+        }
+        if !report::confirm(&format!("Remove all reviewers of {} changes?", changes.len())) {
+            return Ok("Cancelled".to_string());
+        }
+    }
 
-    //usage();
-    let arg = args::parse();
-    env_logger::Builder::new()
-        .filter_level(arg.verbose.log_level_filter())
-        .init();
+    let mut failed = 0;
+    println!("Results:");
+    for (pos, c) in changes.iter().enumerate() {
+        let result = match action {
+            TopicAction::Add { reviewers } => topic::add_reviewers(c, reviewers),
+            TopicAction::Clear if plans[pos].is_empty() => Ok("No reviewers now".to_string()),
+            TopicAction::Clear => gerrit_if::apply_plan(&plans[pos]),
+            TopicAction::List => unreachable!(),
+        };
+        if result.is_err() {
+            failed += 1;
+        }
+        print_result(&format!("{} ({})", c.change_id, c.conn.project()), &result);
+    }
 
-    let gerrit_ctx = gerrit_if::Gerrit::new();
+    if failed > 0 {
+        return Err(format!("Failed on {} of {} changes", failed, changes.len()));
+    }
+    Ok(format!("Done on {} changes", changes.len()))
+}
 
-    let mut tex_ui = tex_ui::TexUI {
-        gerrit_ctx: gerrit_ctx,
+/// Print the changes waiting for the user of the remote
+fn run_inbox(url: Option<&str>, remote: &str) -> Result<String, String> {
+    let conn = gerrit_if::get_remote_conn(url, remote)?;
+    let me = match &conn.username {
+        Some(u) => GerritUser::from_str(u),
+        None => return Err("No username in the remote url to find your changes".to_string()),
     };
-    tex_ui.start(arg);
-    
-    /*
+    report::print_inbox(&inbox::get_inbox(&conn, &me)?);
+    Ok(String::new())
+}
 
-    let change_id = "I1db9608c85fe80b30ffa881b60968d9695a463ff";
-    list_reviews();
-    list_reviewers();
+fn run_command(command: &args::Command, arg: &args::Args) -> i32 {
+    let result = match command {
+        args::Command::Reviewers => {
+            let change = get_gerrit_change(arg);
+            votes::get_reviewer_votes(&change).map(|rows| {
+                report::print_reviewer_votes(&rows);
+                String::new()
+            })
+        },
+        args::Command::Requirements => {
+            let change = get_gerrit_change(arg);
+            requirements::analyze(&change).map(|gaps| {
+                report::print_gaps(&gaps);
+                String::new()
+            })
+        },
+        args::Command::Recent => {
+            let change = get_gerrit_change(arg);
+            match &change.conn.username {
+                Some(u) => gerrit_if::get_user_recent_reviews(&change.conn, &GerritUser::from_str(u)).map(|reviews| {
+                    report::print_reviews(&reviews);
+                    String::new()
+                }),
+                None => Err(format!("Unknown user of {}", change.conn.raw)),
+            }
+        },
+        args::Command::Suggest => {
+            let change = get_gerrit_change(arg);
+            candidates::suggest(&change).map(|rows| {
+                report::print_users(&rows);
+                String::new()
+            })
+        },
+        args::Command::Group { global, action } => {
+            let scope = if *global { ConfigScope::Global } else { ConfigScope::Local };
+            let change = get_gerrit_change(arg);
+            run_group_action(action.clone(), scope, &change)
+        },
+        args::Command::Push { remote, branch, groups, reviewers, cc, recent, push_options, write_trailers } => {
+            let req = push::PushRequest {
+                remote: remote.clone(),
+                branch: branch.clone(),
+                groups: groups.clone(),
+                reviewers: reviewers.clone(),
+                ccs: cc.clone(),
+                recent: *recent,
+                use_push_options: *push_options,
+                write_trailers: *write_trailers,
+            };
+            push::run(&req, arg.url.as_deref())
+        },
+        args::Command::Stack { upstream, related, remote, add, remove, groups } => {
+            let req = stack::StackRequest {
+                upstream: upstream.clone(),
+                related: *related,
+                remote: remote.clone(),
+                add: add.clone(),
+                remove: remove.clone(),
+                groups: groups.clone(),
+            };
+            run_stack(&req, arg)
+        },
+        args::Command::Topic { topic, remote, action } => {
+            run_topic(topic, action, arg.url.as_deref(), remote)
+        },
+        args::Command::History { count } => {
+            report::print_history(*count);
+            Ok(String::new())
+        },
+        args::Command::Undo => journal::undo(),
+        args::Command::ApplyTrailers { commit, remote } => {
+            trailers::apply(commit, arg.url.as_deref(), remote)
+        },
+        args::Command::Inbox { remote } => run_inbox(arg.url.as_deref(), remote),
+        args::Command::Stats { since, until, project, branch, remote, max } => {
+            let req = stats::StatsRequest {
+                since: since.clone(),
+                until: until.clone(),
+                project: project.clone(),
+                branch: branch.clone(),
+                remote: remote.clone(),
+                max: *max,
+            };
+            stats::run(&req, arg.url.as_deref()).map(|stats| {
+                report::print_stats(&stats);
+                String::new()
+            })
+        },
+    };
 
-    for id in get_unmerged_local_changes() {
-        println!("{}", id);
+    match result {
+        Ok(msg) => {
+            if !msg.is_empty() {
+                println!("{}", msg);
+            }
+            0
+        },
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
     }
-    */
-
-
-    0
 }
 
 fn main() {
@@ -90,4 +274,3 @@ fn main() {
 
     std::process::exit(ret);
 }
-
@@ -1,25 +1,278 @@
 use std::str;
-use log::{trace};
-use std::process::Command;
+use log::{trace, debug};
+use std::io::{Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Poll interval of running commands, for timeouts and Ctrl-C
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+// Number of commands running, they may run in worker threads
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// Ctrl-C cancels the running command, or exits when nothing is running
+pub fn install_interrupt_handler() {
+    let ret = ctrlc::set_handler(|| {
+        if RUNNING.load(Ordering::SeqCst) == 0 {
+            std::process::exit(130);
+        }
+        CANCELLED.store(true, Ordering::SeqCst);
+    });
+    if let Err(e) = ret {
+        debug!("Failed to set Ctrl-C handler: {}", e);
+    }
+}
+
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+/// Called before the next action, e.g. when back to the menu
+pub fn reset_cancelled() {
+    CANCELLED.store(false, Ordering::SeqCst);
+}
+
+/// Answers commands instead of running them, e.g. a fake Gerrit in tests
+pub trait CommandHandler: Send + Sync {
+    /// None to run the command for real
+    fn handle(&self, argv: &[String], input: Option<&str>) -> Option<Result<String, CommandError>>;
+}
+
+static HANDLER: RwLock<Option<Arc<dyn CommandHandler>>> = RwLock::new(None);
+
+pub fn set_command_handler(handler: Option<Arc<dyn CommandHandler>>) {
+    *HANDLER.write().unwrap() = handler;
+}
+
+fn handle(argv: &[String], input: Option<&str>) -> Option<Result<String, CommandError>> {
+    let handler = HANDLER.read().unwrap().clone();
+    handler.and_then(|h| h.handle(argv, input))
+}
+
+/// Why a command failed
+#[derive(Debug)]
+pub enum CommandError {
+    Spawn(String),
+    Exit { code: Option<i32>, stderr: String },
+    Timeout(Duration),
+    Cancelled,
+}
+
+impl CommandError {
+    pub fn code(&self) -> Option<i32> {
+        match self {
+            CommandError::Exit { code, .. } => *code,
+            _ => None,
+        }
+    }
+
+    pub fn stderr(&self) -> &str {
+        match self {
+            CommandError::Exit { stderr, .. } => stderr,
+            _ => "",
+        }
+    }
+}
+
+/// External command with an optional timeout, retries and stdin
+pub struct CommandRunner {
+    argv: Vec<String>,
+    input: Option<String>,
+    envs: Vec<(String, String)>,
+    timeout: Option<Duration>,
+    retries: u32,
+    retry_if: fn(&CommandError) -> bool,
+}
+
+impl CommandRunner {
+    pub fn new(cmd_vec: &[&str]) -> Self {
+        Self {
+            argv: cmd_vec.iter().map(|s| s.to_string()).collect(),
+            input: None,
+            envs: Vec::new(),
+            timeout: None,
+            retries: 0,
+            retry_if: |e| matches!(e, CommandError::Timeout(_)),
+        }
+    }
+
+    pub fn input(mut self, input: &str) -> Self {
+        self.input = Some(input.to_string());
+        self
+    }
+
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.envs.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Run again up to `retries` times when `retry_if` tells the failure is transient
+    pub fn retries(mut self, retries: u32, retry_if: fn(&CommandError) -> bool) -> Self {
+        self.retries = retries;
+        self.retry_if = retry_if;
+        self
+    }
+
+    pub fn run(&self) -> Result<String, String> {
+        let mut attempt = 0;
+        loop {
+            match self.run_once() {
+                Ok(out) => return Ok(out),
+                Err(e) if attempt < self.retries && (self.retry_if)(&e) => {
+                    let delay = RETRY_BACKOFF * 2u32.pow(attempt);
+                    attempt += 1;
+                    debug!("{}, retry {}/{} in {:?}", self.describe(&e), attempt, self.retries, delay);
+                    if !sleep(delay) {
+                        return Err(self.describe(&CommandError::Cancelled));
+                    }
+                },
+                // The child may die of the Ctrl-C before it is noticed
+                Err(_) if is_cancelled() => return Err(self.describe(&CommandError::Cancelled)),
+                Err(e) => return Err(self.describe(&e)),
+            }
+        }
+    }
+
+    fn describe(&self, err: &CommandError) -> String {
+        let cmd = &self.argv[0];
+        match err {
+            CommandError::Spawn(e) => format!("Failed to execute {}: {}", cmd, e),
+            CommandError::Exit { code: Some(code), stderr } => format!("{} exited with code {}: {}", cmd, code, stderr.trim_end()),
+            CommandError::Exit { code: None, stderr } => format!("{} was killed: {}", cmd, stderr.trim_end()),
+            CommandError::Timeout(t) => format!("{} timed out after {}s", cmd, t.as_secs()),
+            CommandError::Cancelled => format!("{} cancelled", cmd),
+        }
+    }
+
+    fn run_once(&self) -> Result<String, CommandError> {
+        if is_cancelled() {
+            return Err(CommandError::Cancelled);
+        }
+        trace!("Running: {}", self.argv.join(" "));
+
+        if let Some(ret) = handle(&self.argv, self.input.as_deref()) {
+            return ret;
+        }
+
+        let mut child = Command::new(&self.argv[0])
+            .args(&self.argv[1..])
+            .envs(self.envs.iter().map(|(k, v)| (k, v)))
+            .stdin(if self.input.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| CommandError::Spawn(e.to_string()))?;
+
+        // Pipes are drained by threads, so a large output can not block the child
+        if let (Some(mut stdin), Some(input)) = (child.stdin.take(), self.input.clone()) {
+            thread::spawn(move || stdin.write_all(input.as_bytes()));
+        }
+        let stdout = read_in_thread(child.stdout.take());
+        let stderr = read_in_thread(child.stderr.take());
+
+        let status = wait(&mut child, self.timeout)?;
+        let out = stdout.join().unwrap_or_default();
+        let err = stderr.join().unwrap_or_default();
+
+        if !status.success() {
+            return Err(CommandError::Exit { code: status.code(), stderr: err });
+        }
+        trace!("Stdout: {}", out);
+        Ok(out)
+    }
+}
+
+fn read_in_thread<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut p) = pipe {
+            let _ = p.read_to_end(&mut buf);
+        }
+        String::from_utf8_lossy(&buf).to_string()
+    })
+}
+
+// The child is killed on timeout or Ctrl-C
+fn wait(child: &mut Child, timeout: Option<Duration>) -> Result<ExitStatus, CommandError> {
+    let start = Instant::now();
+    RUNNING.fetch_add(1, Ordering::SeqCst);
+    let ret = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Ok(status),
+            Ok(None) => {},
+            Err(e) => break Err(CommandError::Spawn(e.to_string())),
+        }
+        if is_cancelled() {
+            break Err(CommandError::Cancelled);
+        }
+        if let Some(t) = timeout {
+            if start.elapsed() >= t {
+                break Err(CommandError::Timeout(t));
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    RUNNING.fetch_sub(1, Ordering::SeqCst);
+    if ret.is_err() {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+    ret
+}
+
+// Return false if cancelled while sleeping
+fn sleep(duration: Duration) -> bool {
+    let start = Instant::now();
+    RUNNING.fetch_add(1, Ordering::SeqCst);
+    while start.elapsed() < duration && !is_cancelled() {
+        thread::sleep(POLL_INTERVAL);
+    }
+    RUNNING.fetch_sub(1, Ordering::SeqCst);
+    !is_cancelled()
+}
 
 pub fn run_command_exc(cmd_vec : Vec<&str>) -> Result<String, String> {
+    CommandRunner::new(&cmd_vec).run()
+}
+
+
+// Output goes to the terminal, e.g. messages of git push
+pub fn run_command_inherit(cmd_vec : Vec<&str>) -> Result<(), String> {
 
     trace!("Running: {}", cmd_vec.join(" "));
 
+    let argv: Vec<String> = cmd_vec.iter().map(|s| s.to_string()).collect();
+    if let Some(ret) = handle(&argv, None) {
+        return ret.map(|_| ()).map_err(|e| format!("{} failed: {:?}", cmd_vec[0], e));
+    }
+
     let cmd = cmd_vec[0];
-    let result = Command::new(cmd)
+    let status = Command::new(cmd)
         .args(&cmd_vec[1..])
-        .output()
-        .expect("failed to execute process");
+        .status()
+        .map_err(|e| format!("Failed to execute {}: {}", cmd, e))?;
 
-    if !result.status.success() {
-        let err_msg = str::from_utf8(&result.stderr).expect("Invalid UTF8-8 sequence");
-        return Err(err_msg.to_string())
+    if !status.success() {
+        return Err(format!("{} exited with {}", cmd, status));
     }
-
-    let out = str::from_utf8(&result.stdout).expect("Invalid UTF8-8 sequence");
-    // TODO json pretty print
-    trace!("Stdout: {}", out);
-    Ok(out.to_string())
+    Ok(())
 }
 
+// Input is written to stdin, e.g. secrets which must not show up in the arguments
+pub fn run_command_input(cmd_vec : Vec<&str>, input : &str, envs : &[(&str, &str)]) -> Result<String, String> {
+    let mut runner = CommandRunner::new(&cmd_vec).input(input);
+    for (k, v) in envs {
+        runner = runner.env(k, v);
+    }
+    runner.run()
+}
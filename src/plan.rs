@@ -0,0 +1,119 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::os;
+use crate::gerrit_if::GerritChange;
+use crate::users::GerritUser;
+
+// Set once from the command line before any mutation
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+pub fn set_dry_run(dry_run: bool) {
+    DRY_RUN.store(dry_run, Ordering::Relaxed);
+}
+
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// Reviewer changes to make on one change
+pub struct ReviewerPlan<'a> {
+    pub change: &'a GerritChange,
+    pub adds: Vec<GerritUser>,
+    /// Gerrit groups whose members Gerrit adds as reviewers
+    pub groups: Vec<String>,
+    pub removes: Vec<GerritUser>,
+    pub ccs: Vec<GerritUser>,
+    /// CCs to remove, e.g. on undo
+    pub cc_removes: Vec<GerritUser>,
+}
+
+impl<'a> ReviewerPlan<'a> {
+    pub fn new(change: &'a GerritChange) -> Self {
+        Self {
+            change,
+            adds: Vec::new(),
+            groups: Vec::new(),
+            removes: Vec::new(),
+            ccs: Vec::new(),
+            cc_removes: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.adds.is_empty() && self.groups.is_empty() && self.removes.is_empty() && self.ccs.is_empty()
+            && self.cc_removes.is_empty()
+    }
+
+    /// Plan without adding current reviewers or removing users who are not
+    /// reviewers, with a note on each user left out
+    pub fn prune(&self, cur_reviewers: &[GerritUser]) -> (ReviewerPlan<'a>, Vec<String>) {
+        let mut plan = ReviewerPlan::new(self.change);
+        let mut notes = Vec::new();
+        let is_reviewer = |user: &GerritUser| cur_reviewers.iter().any(|r| r.same_account(user));
+        for user in &self.adds {
+            if is_reviewer(user) {
+                notes.push(format!("{} is already a reviewer", user));
+            } else {
+                plan.adds.push(user.clone());
+            }
+        }
+        for user in &self.removes {
+            if is_reviewer(user) {
+                plan.removes.push(user.clone());
+            } else {
+                notes.push(format!("{} is not a reviewer", user));
+            }
+        }
+        plan.groups = self.groups.clone();
+        plan.ccs = self.ccs.clone();
+        plan.cc_removes = self.cc_removes.clone();
+        (plan, notes)
+    }
+
+    /// Reviewers after the plan is applied to the current reviewers,
+    /// without the members of the groups which only Gerrit knows
+    pub fn apply_to(&self, cur_reviewers: &[GerritUser]) -> Vec<GerritUser> {
+        let mut after: Vec<GerritUser> = cur_reviewers.iter()
+            .filter(|u| !self.removes.iter().any(|r| r.same_account(u)))
+            .cloned()
+            .collect();
+        for u in &self.adds {
+            if !after.iter().any(|a| a.same_account(u)) {
+                after.push(u.clone());
+            }
+        }
+        after
+    }
+
+    pub fn describe(&self) -> String {
+        let names = |users: &Vec<GerritUser>| {
+            users.iter().map(|u| u.to_string()).collect::<Vec<String>>().join(", ")
+        };
+        let mut s = format!("Change {}:", self.change.change_id);
+        if !self.adds.is_empty() {
+            s.push_str(&format!("\n  add: {}", names(&self.adds)));
+        }
+        if !self.groups.is_empty() {
+            s.push_str(&format!("\n  add groups: {}", self.groups.join(", ")));
+        }
+        if !self.removes.is_empty() {
+            s.push_str(&format!("\n  remove: {}", names(&self.removes)));
+        }
+        if !self.ccs.is_empty() {
+            s.push_str(&format!("\n  cc: {}", names(&self.ccs)));
+        }
+        if !self.cc_removes.is_empty() {
+            s.push_str(&format!("\n  remove cc: {}", names(&self.cc_removes)));
+        }
+        s
+    }
+}
+
+/// Run a command which changes the state of Gerrit or the repository,
+/// only return it as "Would run: ..." in dry-run mode
+pub fn run_mutation(cmd_vec: Vec<&str>) -> Result<String, String> {
+    if is_dry_run() {
+        return Ok(format!("Would run: {}", cmd_vec.join(" ")));
+    }
+    os::run_command_exc(cmd_vec)
+}
@@ -0,0 +1,71 @@
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use log::warn;
+
+use crate::config;
+
+const DEFAULT_JOBS: usize = 4;
+
+static JOBS: OnceLock<usize> = OnceLock::new();
+
+/// Number of concurrent queries, `gerreviewer.jobs`
+pub fn jobs() -> usize {
+    *JOBS.get_or_init(|| match config::get_setting("jobs") {
+        Some(s) => match s.parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => {
+                warn!("Invalid jobs: {}", s);
+                DEFAULT_JOBS
+            },
+        },
+        None => DEFAULT_JOBS,
+    })
+}
+
+/// Apply f to every item with at most `workers` threads, results keep the order of items
+pub fn map<T, R, F>(items: Vec<T>, workers: usize, f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    let workers = workers.min(items.len());
+    if workers <= 1 {
+        return items.into_iter().map(f).collect();
+    }
+
+    let len = items.len();
+    let queue: Mutex<Vec<(usize, T)>> = Mutex::new(items.into_iter().enumerate().rev().collect());
+    let results: Mutex<Vec<Option<R>>> = Mutex::new((0..len).map(|_| None).collect());
+
+    thread::scope(|s| {
+        for _ in 0..workers {
+            s.spawn(|| loop {
+                let next = queue.lock().unwrap().pop();
+                let (pos, item) = match next {
+                    Some(n) => n,
+                    None => break,
+                };
+                let r = f(item);
+                results.lock().unwrap()[pos] = Some(r);
+            });
+        }
+    });
+
+    results.into_inner().unwrap().into_iter().map(|r| r.unwrap()).collect()
+}
+
+/// Run two independent queries at the same time
+pub fn join<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA + Send,
+    B: FnOnce() -> RB + Send,
+    RA: Send,
+    RB: Send,
+{
+    thread::scope(|s| {
+        let handle = s.spawn(b);
+        let ra = a();
+        (ra, handle.join().unwrap())
+    })
+}
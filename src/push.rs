@@ -0,0 +1,131 @@
+use log::info;
+use linked_hash_set::LinkedHashSet;
+
+use crate::git;
+use crate::gerrit_if;
+use crate::users::{GerritUser, insert_user};
+use crate::candidates::CandidateFilter;
+use crate::availability::Availability;
+use crate::selection;
+use crate::groups::GroupRef;
+use crate::trailers;
+use crate::plan;
+
+pub struct PushRequest {
+    pub remote: String,
+    pub branch: Option<String>,
+    pub groups: Vec<String>,
+    pub reviewers: Vec<String>,
+    pub ccs: Vec<String>,
+    /// Number of recent reviewers of the user to add
+    pub recent: usize,
+    pub use_push_options: bool,
+    /// Write the chosen reviewers into the commit message before pushing
+    pub write_trailers: bool,
+}
+
+/// Compute reviewers from groups and recent reviewers, then push HEAD for
+/// review so the reviewers are attached at upload
+pub fn run(req: &PushRequest, url: Option<&str>) -> Result<String, String> {
+    let conn = gerrit_if::get_remote_conn(url, &req.remote)?;
+
+    let branch = match &req.branch {
+        Some(b) => b.clone(),
+        None => match git::get_upstream_branch(&req.remote) {
+            Some(b) => b,
+            None => return Err("No upstream branch of HEAD, use --branch".to_string()),
+        },
+    };
+    info!("Push to {} branch {}", req.remote, branch);
+
+    // Reviewers declared in the commit message, "@group" picks from the group
+    let mut groups = req.groups.clone();
+    let mut gerrit_groups = Vec::new();
+    let mut names = req.reviewers.clone();
+    let mut ccs = req.ccs.clone();
+    let mut declared = Vec::new();
+    if let Some(trailers) = git::get_local_commit_trailers("HEAD") {
+        declared = trailers.reviewers.clone();
+        for r in trailers.reviewers {
+            match GroupRef::parse(&r) {
+                Some(GroupRef::Local(g)) => groups.push(g),
+                Some(GroupRef::Gerrit(g)) => gerrit_groups.push(g),
+                None => names.push(r),
+            }
+        }
+        ccs.extend(trailers::expand_ccs(&trailers.ccs, &conn)?);
+    }
+
+    let owner = conn.username.as_ref().map(|u| GerritUser::from_str(u));
+    let filter = CandidateFilter::for_owner(owner.clone());
+
+    // Told before the result, e.g. who is skipped
+    let mut notes = Vec::new();
+    let mut reviewers = LinkedHashSet::new();
+    let (named, skipped) = filter.partition(names.iter().map(|r| GerritUser::from_str(r)).collect());
+    notes.extend(skipped.iter().map(|s| s.describe()));
+    for r in named {
+        insert_user(&mut reviewers, r);
+    }
+
+    let mut history = selection::AssignmentHistory::new();
+    let mut picked = Vec::new();
+    for g in &groups {
+        let pick = selection::pick_from_group(g, &conn, &filter, &history)?;
+        notes.extend(pick.skipped.iter().map(|s| s.describe()));
+        for u in &pick.users {
+            insert_user(&mut reviewers, u.clone());
+        }
+        gerrit_groups.extend(pick.groups);
+        picked.push((g, pick.users));
+    }
+
+    // Reviewers at upload are accounts, so Gerrit groups are expanded here
+    for g in &gerrit_groups {
+        let (members, skipped) = filter.partition(gerrit_if::get_group_members(&conn, g)?);
+        notes.extend(skipped.iter().map(|s| s.describe()));
+        for u in members {
+            insert_user(&mut reviewers, u);
+        }
+    }
+
+    if req.recent > 0 {
+        if let Some(owner) = &owner {
+            let recent = gerrit_if::get_user_recent_reviewers(&conn, owner)?;
+            let (recent, away) = Availability::new(&conn).partition(filter.apply(recent));
+            notes.extend(away.iter().map(|s| s.describe()));
+            for u in recent.into_iter().take(req.recent) {
+                insert_user(&mut reviewers, u);
+            }
+        } else {
+            notes.push(format!("Unknown user of {}, skip recent reviewers", conn.raw));
+        }
+    }
+
+    let reviewers: Vec<String> = reviewers.iter().map(|u| u.account()).collect();
+    if req.write_trailers {
+        let new_reviewers: Vec<String> = reviewers.iter()
+            .filter(|r| !declared.contains(r))
+            .cloned()
+            .collect();
+        let amended = git::amend_reviewer_trailers(&new_reviewers, &req.ccs)?;
+        if plan::is_dry_run() {
+            notes.push(amended);
+        }
+    }
+    let pushed = git::push_for_review(&req.remote, &branch, &reviewers, &ccs, req.use_push_options)?;
+
+    for (g, users) in picked {
+        history.record(g, &users);
+    }
+    if let Err(err) = history.save() {
+        notes.push(format!("Failed to save assignment history: {}", err));
+    }
+    if plan::is_dry_run() {
+        notes.push(pushed);
+        notes.push(format!("Dry run, reviewers: {}", reviewers.join(", ")));
+    } else {
+        notes.push(format!("Pushed with reviewers: {}", reviewers.join(", ")));
+    }
+    Ok(notes.join("\n"))
+}
@@ -0,0 +1,549 @@
+use std::io;
+use std::sync::atomic::{AtomicU8, Ordering};
+use log::error;
+use clap::ValueEnum;
+use linked_hash_set::LinkedHashSet;
+use serde_json::{json, Value};
+
+use git_reviewers::gerrit_if::GerritChange;
+use git_reviewers::candidates::UserRow;
+use git_reviewers::config;
+use git_reviewers::inbox::InboxEntry;
+use git_reviewers::journal;
+use git_reviewers::plan;
+use git_reviewers::requirements::Gap;
+use git_reviewers::selection::SelectMode;
+use git_reviewers::stack::StackChange;
+use git_reviewers::stats;
+use git_reviewers::stats::StatsReport;
+use git_reviewers::users::GerritUser;
+use git_reviewers::votes::ReviewerVotes;
+
+use serde_json::Value as Review;
+
+/// Output of listing commands
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum)]
+pub enum OutputFormat {
+    /// Text for humans
+    Plain,
+    /// JSON array
+    Json,
+    /// Tab separated values with a header line
+    Tsv,
+}
+
+// Set once from the command line
+static FORMAT: AtomicU8 = AtomicU8::new(OutputFormat::Plain as u8);
+
+pub fn set_format(format: OutputFormat) {
+    FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+pub fn format() -> OutputFormat {
+    match FORMAT.load(Ordering::Relaxed) {
+        x if x == OutputFormat::Json as u8 => OutputFormat::Json,
+        x if x == OutputFormat::Tsv as u8 => OutputFormat::Tsv,
+        _ => OutputFormat::Plain,
+    }
+}
+
+pub fn is_plain() -> bool {
+    format() == OutputFormat::Plain
+}
+
+// Keep one record per line
+fn tsv_field(s: &str) -> String {
+    s.replace(['\t', '\n', '\r'], " ")
+}
+
+fn print_tsv(header: &[&str], rows: Vec<Vec<String>>) {
+    println!("{}", header.join("\t"));
+    for row in rows {
+        let fields: Vec<String> = row.iter().map(|f| tsv_field(f)).collect();
+        println!("{}", fields.join("\t"));
+    }
+}
+
+const USER_HEADER: [&str; 6] = ["username", "email", "name", "state", "score", "reasons"];
+
+/// Formats of a user row in listings
+trait RowFormat {
+    fn to_json(&self) -> Value;
+    fn to_fields(&self) -> Vec<String>;
+    fn to_plain(&self) -> String;
+}
+
+impl RowFormat for UserRow {
+    fn to_json(&self) -> Value {
+        json!({
+            "username": self.user.username,
+            "email": self.user.email,
+            "name": self.user.full_name,
+            "state": self.state,
+            "score": self.score,
+            "reasons": self.reasons,
+        })
+    }
+
+    fn to_fields(&self) -> Vec<String> {
+        vec![
+            self.user.username.clone(),
+            self.user.email.clone(),
+            self.user.full_name.clone(),
+            self.state.clone(),
+            self.score.map(|s| s.to_string()).unwrap_or_default(),
+            self.reasons.join("; "),
+        ]
+    }
+
+    fn to_plain(&self) -> String {
+        let mut s = self.user.as_string();
+        if !self.reasons.is_empty() {
+            s.push_str(&format!(" ({})", self.reasons.join("; ")));
+        }
+        s
+    }
+}
+
+pub fn print_users(rows: &[UserRow]) {
+    match format() {
+        OutputFormat::Plain => {
+            for r in rows {
+                println!("* {}", r.to_plain());
+            }
+        },
+        OutputFormat::Json => {
+            let json: Vec<Value> = rows.iter().map(|r| r.to_json()).collect();
+            println!("{}", Value::Array(json));
+        },
+        OutputFormat::Tsv => {
+            print_tsv(&USER_HEADER, rows.iter().map(|r| r.to_fields()).collect());
+        },
+    }
+}
+
+/// Users of several changes, e.g. reviewers of each change of a topic
+pub fn print_change_users(changes: &[(&GerritChange, Vec<UserRow>)]) {
+    match format() {
+        OutputFormat::Plain => {
+            for (c, rows) in changes {
+                println!("{} ({}):", c.change_id, c.conn.project());
+                for r in rows {
+                    println!("  * {}", r.to_plain());
+                }
+            }
+        },
+        OutputFormat::Json => {
+            let json: Vec<Value> = changes.iter().map(|(c, rows)| json!({
+                "change": c.change_id,
+                "project": c.conn.project(),
+                "users": rows.iter().map(|r| r.to_json()).collect::<Vec<Value>>(),
+            })).collect();
+            println!("{}", Value::Array(json));
+        },
+        OutputFormat::Tsv => {
+            let mut header = vec!["change", "project"];
+            header.extend(USER_HEADER);
+            let mut lines = Vec::new();
+            for (c, rows) in changes {
+                for r in rows {
+                    let mut fields = vec![c.change_id.clone(), c.conn.project().to_string()];
+                    fields.extend(r.to_fields());
+                    lines.push(fields);
+                }
+            }
+            print_tsv(&header, lines);
+        },
+    }
+}
+
+pub fn print_reviews(reviews: &[Review]) {
+    let field = |r: &Review, key: &str| r[key].as_str().unwrap_or_default().to_string();
+    let number = |r: &Review| match r["number"].as_u64() {
+        Some(n) => n.to_string(),
+        None => field(r, "number"),
+    };
+    let owner = |r: &Review| r["owner"]["username"].as_str().unwrap_or_default().to_string();
+
+    match format() {
+        OutputFormat::Plain => {
+            for r in reviews {
+                println!("{}/{} | {} | {}", field(r, "project"), field(r, "branch"), owner(r), field(r, "subject"));
+            }
+        },
+        OutputFormat::Json => {
+            let json: Vec<Value> = reviews.iter().map(|r| json!({
+                "number": number(r),
+                "project": field(r, "project"),
+                "branch": field(r, "branch"),
+                "owner": owner(r),
+                "subject": field(r, "subject"),
+            })).collect();
+            println!("{}", Value::Array(json));
+        },
+        OutputFormat::Tsv => {
+            let rows = reviews.iter().map(|r| vec![
+                number(r), field(r, "project"), field(r, "branch"), owner(r), field(r, "subject"),
+            ]).collect();
+            print_tsv(&["number", "project", "branch", "owner", "subject"], rows);
+        },
+    }
+}
+
+/// Records of any other listing, in the order of the header
+pub fn print_records(header: &[&str], rows: Vec<Vec<String>>) {
+    match format() {
+        OutputFormat::Plain | OutputFormat::Tsv => print_tsv(header, rows),
+        OutputFormat::Json => {
+            let json: Vec<Value> = rows.iter().map(|row| {
+                let obj = header.iter().zip(row)
+                    .map(|(k, v)| (k.to_string(), Value::String(v.clone())))
+                    .collect();
+                Value::Object(obj)
+            }).collect();
+            println!("{}", Value::Array(json));
+        },
+    }
+}
+
+/// Columns aligned for humans, numbers to the right
+pub fn print_table(header: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (w, f) in widths.iter_mut().zip(row) {
+            *w = (*w).max(f.chars().count());
+        }
+    }
+    let is_number = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    let line = |fields: Vec<&str>| {
+        let cells: Vec<String> = fields.iter().zip(&widths)
+            .map(|(f, w)| if is_number(f) { format!("{:>w$}", f, w = w) } else { format!("{:<w$}", f, w = w) })
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    };
+    line(header.to_vec());
+    for row in rows {
+        line(row.iter().map(|f| f.as_str()).collect());
+    }
+}
+
+/// Reviewers with their votes on the current patch set
+pub fn print_reviewer_votes(rows: &[ReviewerVotes]) {
+    match format() {
+        OutputFormat::Plain => {
+            if rows.is_empty() {
+                println!("* No reviewers!");
+            }
+            for r in rows {
+                let mut details: Vec<String> = r.votes.iter().map(|v| v.describe()).collect();
+                if r.commented {
+                    details.push("commented".to_string());
+                }
+                if details.is_empty() {
+                    println!("* {}", r.user.as_string());
+                } else {
+                    println!("* {}: {}", r.user.as_string(), details.join(", "));
+                }
+            }
+        },
+        OutputFormat::Json => {
+            let json: Vec<Value> = rows.iter().map(|r| json!({
+                "username": r.user.username,
+                "email": r.user.email,
+                "name": r.user.full_name,
+                "votes": r.votes.iter().map(|v| json!({
+                    "label": v.label,
+                    "value": v.value,
+                    "patch_set": v.patch_set,
+                    "stale": v.stale,
+                })).collect::<Vec<Value>>(),
+                "commented": r.commented,
+            })).collect();
+            println!("{}", Value::Array(json));
+        },
+        OutputFormat::Tsv => {
+            let lines = rows.iter().map(|r| vec![
+                r.user.account(),
+                r.user.email.clone(),
+                r.user.full_name.clone(),
+                r.votes.iter().map(|v| v.describe()).collect::<Vec<String>>().join("; "),
+                r.commented.to_string(),
+            ]).collect();
+            print_records(&["username", "email", "name", "votes", "commented"], lines);
+        },
+    }
+}
+
+pub fn print_gaps(gaps: &[Gap]) {
+    match format() {
+        OutputFormat::Plain => {
+            println!("Submit requirements:");
+            for g in gaps {
+                let r = &g.requirement;
+                if r.satisfied {
+                    println!("  OK      {} ({})", r.name, r.status);
+                    continue;
+                }
+                println!("  MISSING {} ({}): {}", r.name, r.status, g.describe_need());
+                if r.label.is_none() {
+                    continue;
+                }
+                if !g.reviewers.is_empty() {
+                    println!("    Reviewers who can vote:");
+                    for row in &g.reviewers {
+                        println!("      * {}", row.to_plain());
+                    }
+                }
+                if g.candidates.is_empty() {
+                    println!("    No other eligible candidate found");
+                } else {
+                    println!("    Candidates:");
+                    for c in &g.candidates {
+                        println!("      * {}", c.row.to_plain());
+                    }
+                }
+            }
+        },
+        OutputFormat::Json => {
+            let json: Vec<Value> = gaps.iter().map(|g| json!({
+                "name": g.requirement.name,
+                "status": g.requirement.status,
+                "satisfied": g.requirement.satisfied,
+                "label": g.requirement.label,
+                "needed": g.needed,
+                "reviewers": g.reviewers.iter().map(|r| r.to_json()).collect::<Vec<Value>>(),
+                "candidates": g.candidates.iter().map(|c| c.row.to_json()).collect::<Vec<Value>>(),
+            })).collect();
+            println!("{}", Value::Array(json));
+        },
+        OutputFormat::Tsv => {
+            let mut header = vec!["requirement", "status", "label", "needed"];
+            header.extend(["username", "email", "name", "state", "score", "reasons"]);
+            let mut lines = Vec::new();
+            for g in gaps {
+                let fields = vec![
+                    g.requirement.name.clone(),
+                    g.requirement.status.clone(),
+                    g.requirement.label.clone().unwrap_or_default(),
+                    g.needed.map(|n| n.to_string()).unwrap_or_default(),
+                ];
+                let rows: Vec<&UserRow> = g.reviewers.iter().chain(g.candidates.iter().map(|c| &c.row)).collect();
+                if rows.is_empty() {
+                    let mut line = fields.clone();
+                    line.extend(vec![String::new(); 6]);
+                    lines.push(line);
+                }
+                for row in rows {
+                    let mut line = fields.clone();
+                    line.extend(row.to_fields());
+                    lines.push(line);
+                }
+            }
+            print_records(&header, lines);
+        },
+    }
+}
+
+pub fn print_stats(report: &StatsReport) {
+    if is_plain() {
+        println!("{} changes of {} {}", report.changes, report.project, report.range);
+    }
+    let stats = &report.stats;
+    let header = ["reviewer", "reviews", "votes", "median_first_review", "open_queue"];
+    match format() {
+        OutputFormat::Json => {
+            let json: Vec<Value> = stats.iter().map(|s| json!({
+                "username": s.user.username,
+                "email": s.user.email,
+                "name": s.user.full_name,
+                "reviews": s.reviews,
+                "votes": s.votes,
+                "median_first_review_secs": s.median_first_review(),
+                "open_queue": s.open_queue,
+            })).collect();
+            println!("{}", Value::Array(json));
+        },
+        OutputFormat::Tsv => {
+            let rows = stats.iter().map(|s| vec![
+                s.user.account(),
+                s.reviews.to_string(),
+                s.votes.to_string(),
+                s.median_first_review().map(|m| m.to_string()).unwrap_or_default(),
+                s.open_queue.to_string(),
+            ]).collect();
+            print_records(&header, rows);
+        },
+        OutputFormat::Plain => {
+            let rows: Vec<Vec<String>> = stats.iter().map(|s| vec![
+                s.user.account(),
+                s.reviews.to_string(),
+                s.votes.to_string(),
+                s.median_first_review().map(stats::format_duration).unwrap_or_else(|| "-".to_string()),
+                s.open_queue.to_string(),
+            ]).collect();
+            print_table(&header, &rows);
+        },
+    }
+}
+
+pub fn print_inbox(entries: &[InboxEntry]) {
+    let header = ["#", "number", "project", "branch", "owner", "age", "my_vote", "attention", "subject"];
+    match format() {
+        OutputFormat::Json => {
+            let json: Vec<Value> = entries.iter().map(|e| json!({
+                "number": e.number,
+                "project": e.change.conn.project(),
+                "branch": e.branch,
+                "owner": e.owner.account(),
+                "age_secs": e.age,
+                "my_votes": e.my_votes,
+                "attention": e.attention,
+                "subject": e.subject,
+            })).collect();
+            println!("{}", Value::Array(json));
+        },
+        format => {
+            let rows: Vec<Vec<String>> = entries.iter().enumerate().map(|(pos, e)| vec![
+                (pos + 1).to_string(),
+                e.number.clone(),
+                e.change.conn.project().to_string(),
+                e.branch.clone(),
+                e.owner.account(),
+                match (format, e.age) {
+                    (OutputFormat::Tsv, Some(a)) => a.to_string(),
+                    (_, Some(a)) => stats::format_duration(a),
+                    (_, None) => String::new(),
+                },
+                e.my_votes.join(" "),
+                if e.attention { "yes".to_string() } else { String::new() },
+                e.subject.clone(),
+            ]).collect();
+            if format == OutputFormat::Tsv {
+                print_records(&header, rows);
+            } else if rows.is_empty() {
+                println!("No changes waiting for you");
+            } else {
+                print_table(&header, &rows);
+            }
+        },
+    }
+}
+
+/// Print the latest entries, newest first
+pub fn print_history(num: usize) {
+    let entries = journal::load();
+    if !is_plain() {
+        let rows = entries.iter().rev().take(num).map(|e| vec![
+            e.time.to_string(), e.remote.clone(), e.project.clone(), e.change.clone(),
+            e.added().join(","), e.removed().join(","), e.ccs.join(","), e.undone.to_string(),
+        ]).collect();
+        print_records(&["time", "remote", "project", "change", "added", "removed", "ccs", "undone"], rows);
+        return;
+    }
+    if entries.is_empty() {
+        println!("No reviewer changes recorded");
+    }
+    for e in entries.iter().rev().take(num) {
+        println!("* {}", e.describe());
+    }
+}
+
+/// Groups of every config, local ones first
+pub fn print_groups() {
+    let configs = config::get_group_configs();
+
+    if !is_plain() {
+        let mut rows = Vec::new();
+        for c in &configs {
+            let scope = format!("{:?}", c.scope).to_lowercase();
+            for g in &c.groups {
+                let members = g.users.iter().map(|u| u.to_identifier().to_string())
+                    .chain(g.includes.iter().map(|r| r.to_string()));
+                for m in members {
+                    rows.push(vec![scope.clone(), g.name.clone(), g.mode.name(), g.pick.to_string(), m]);
+                }
+            }
+        }
+        print_records(&["scope", "group", "mode", "pick", "member"], rows);
+        return;
+    }
+
+    let mut group_sum = 0;
+    for c in &configs {
+        if c.groups.is_empty() {
+            continue;
+        }
+        println!("Config: {}", &c.file_path);
+        for g in &c.groups {
+            group_sum += 1;
+            if g.mode == SelectMode::All {
+                println!("* Group: {}", &g.name);
+            } else {
+                println!("* Group: {} (pick {}, {})", &g.name, g.pick, g.mode.name());
+            }
+            for u in &g.users {
+                println!("  * {}", u.as_string());
+            }
+            for r in &g.includes {
+                println!("  * {}", r);
+            }
+        }
+    }
+
+    if group_sum == 0 {
+        error!("Get no group");
+    }
+}
+
+/// Print reviewers of the changes as a matrix, one column per change
+pub fn print_stack(stack: &[StackChange], reviewers: &[Vec<GerritUser>]) {
+    if !is_plain() {
+        let rows: Vec<(&GerritChange, Vec<UserRow>)> = stack.iter().zip(reviewers)
+            .map(|(c, rs)| (&c.change, rs.iter().map(|r| UserRow::new(r.clone(), "REVIEWER")).collect()))
+            .collect();
+        print_change_users(&rows);
+        return;
+    }
+
+    println!("Changes:");
+    for (pos, c) in stack.iter().enumerate() {
+        println!("  {}: {} {}", pos + 1, c.change.change_id, c.subject);
+    }
+
+    let mut all = LinkedHashSet::new();
+    for r in reviewers.iter().flatten() {
+        all.insert_if_absent(r.clone());
+    }
+    if all.is_empty() {
+        println!("* No reviewers!");
+        return;
+    }
+
+    let width = all.iter().map(|u| u.account().chars().count()).max().unwrap_or(0);
+    let header: Vec<String> = (1..=stack.len()).map(|i| format!("{:>3}", i)).collect();
+    println!("{:width$} {}", "", header.join(""), width = width);
+    for u in &all {
+        let cells: Vec<String> = reviewers.iter()
+            .map(|rs| if rs.contains(u) { format!("{:>3}", "x") } else { format!("{:>3}", ".") })
+            .collect();
+        println!("{:width$} {}", u.account(), cells.join(""), width = width);
+    }
+}
+
+/// Ask before a destructive action, nothing is destroyed in dry-run mode
+pub fn confirm(prompt: &str) -> bool {
+    if plan::is_dry_run() {
+        return true;
+    }
+    matches!(read_line(&format!("{} [y/N]", prompt)).as_str(), "y" | "Y" | "yes")
+}
+
+/// Line typed by the user after the prompt if any, trimmed
+pub fn read_line(prompt: &str) -> String {
+    let mut input = String::new();
+    if !prompt.is_empty() {
+        println!("{}", prompt);
+    }
+    io::stdin().read_line(&mut input).expect("Failed to read line");
+    input.trim().to_string()
+}
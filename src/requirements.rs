@@ -0,0 +1,337 @@
+use std::path::Path;
+use log::{debug, info, warn};
+use regex::Regex;
+use serde_json::Value;
+
+use crate::config;
+use crate::gerrit_if;
+use crate::gerrit_if::GerritChange;
+use crate::git;
+use crate::groups::GroupExpander;
+use crate::candidates::{CandidateFilter, UserRow};
+use crate::ssh;
+use crate::users;
+use crate::users::{GerritUser, UserIdentifier};
+
+use serde_json::Value as Review;
+
+// Merged changes of the branch looked at to find who gives which votes
+const HISTORY_CHANGES: usize = 100;
+const OWNERS_NAME: &str = "OWNERS";
+
+/// Submit requirement of the change, or label of a submit record on older Gerrit
+pub struct Requirement {
+    pub name: String,
+    pub status: String,
+    pub satisfied: bool,
+    /// Label whose vote satisfies the requirement, None if no vote can, e.g. unresolved comments
+    pub label: Option<String>,
+    /// Vote needed on the label, None for the highest value
+    pub needed: Option<i64>,
+}
+
+/// Unsatisfied requirement with the users able to satisfy it
+pub struct Gap {
+    pub requirement: Requirement,
+    pub needed: Option<i64>,
+    /// Current reviewers who can give the vote
+    pub reviewers: Vec<UserRow>,
+    /// Other users who can give the vote, those of groups and OWNERS first
+    pub candidates: Vec<Candidate>,
+}
+
+/// User who can give the vote and is not a reviewer yet
+pub struct Candidate {
+    pub row: UserRow,
+    /// Groups and OWNERS files naming the user, empty for voters on the branch only
+    pub named_by: Vec<String>,
+}
+
+// e.g. "label:Code-Review=MAX AND -label:Code-Review=MIN", negated atoms do not count
+fn label_of_expression(expression: &str) -> Option<(String, Option<i64>)> {
+    let atom = Regex::new(r"(?:^|[\s(])label\d?:([A-Za-z0-9-]+)=(MAX|\+?\d+)").unwrap();
+    let caps = atom.captures(expression)?;
+    let needed = match &caps[2] {
+        "MAX" => None,
+        v => v.trim_start_matches('+').parse().ok(),
+    };
+    Some((caps[1].to_string(), needed))
+}
+
+pub fn parse_requirements(review: &Review) -> Vec<Requirement> {
+    let mut ret = Vec::new();
+    // Gerrit 3.5 and later over HTTP
+    for sr in review["submitRequirements"].as_array().into_iter().flatten() {
+        let status = sr["status"].as_str().unwrap_or_default().to_string();
+        let expression = sr["submittability_expression_result"]["expression"].as_str().unwrap_or_default();
+        let (label, needed) = match label_of_expression(expression) {
+            Some((l, n)) => (Some(l), n),
+            None => (None, None),
+        };
+        ret.push(Requirement {
+            name: sr["name"].as_str().unwrap_or_default().to_string(),
+            satisfied: !matches!(status.as_str(), "UNSATISFIED" | "ERROR"),
+            status,
+            label,
+            needed,
+        });
+    }
+    if !ret.is_empty() {
+        return ret;
+    }
+    // Submit records of `gerrit query --submit-records`
+    for record in review["submitRecords"].as_array().into_iter().flatten() {
+        for l in record["labels"].as_array().into_iter().flatten() {
+            let label = l["label"].as_str().unwrap_or_default().to_string();
+            let status = l["status"].as_str().unwrap_or_default().to_string();
+            ret.push(Requirement {
+                name: label.clone(),
+                satisfied: matches!(status.as_str(), "OK" | "MAY"),
+                status,
+                label: Some(label),
+                needed: None,
+            });
+        }
+    }
+    ret
+}
+
+fn glob_matches(glob: &str, name: &str) -> bool {
+    let pattern = format!("^{}$", regex::escape(glob).replace(r"\*", "[^/]*"));
+    Regex::new(&pattern).map(|re| re.is_match(name)).unwrap_or(false)
+}
+
+/// Owners of the file from OWNERS files of its directory and the parents,
+/// up to a `set noparent`. `read` returns the OWNERS file at a path of the
+/// tree. Each owner comes with the directory which names it.
+pub fn owners_of_file(read: impl Fn(&Path) -> Option<String>, file: &str) -> Vec<(GerritUser, String)> {
+    let mut ret = Vec::new();
+    let name = file.rsplit('/').next().unwrap_or(file);
+    let mut dir = Path::new(file).parent();
+    while let Some(d) = dir {
+        let path = d.join(OWNERS_NAME);
+        let mut noparent = false;
+        if let Some(contents) = read(&path) {
+            let shown = if d.as_os_str().is_empty() { "/".to_string() } else { d.display().to_string() };
+            for line in contents.lines() {
+                let line = line.split('#').next().unwrap_or_default().trim();
+                let owners: Vec<&str> = if line.is_empty() || line == "*" {
+                    continue;
+                } else if line == "set noparent" {
+                    noparent = true;
+                    continue;
+                } else if let Some(rule) = line.strip_prefix("per-file ") {
+                    match rule.split_once('=') {
+                        Some((globs, owners)) if globs.split(',').any(|g| glob_matches(g.trim(), name)) => {
+                            owners.split(',').map(|o| o.trim()).collect()
+                        },
+                        _ => continue,
+                    }
+                } else if line.starts_with("include ") || line.starts_with("file:") {
+                    debug!("{}: {} is not followed", path.display(), line);
+                    continue;
+                } else {
+                    vec![line]
+                };
+                for o in owners.into_iter().filter(|o| !o.is_empty() && *o != "*") {
+                    match UserIdentifier::parse(o) {
+                        Some(id) => ret.push((id.to_user(), shown.clone())),
+                        None => warn!("Invalid owner in {}: {}", path.display(), o),
+                    }
+                }
+            }
+        }
+        if noparent {
+            break;
+        }
+        dir = d.parent();
+    }
+    ret
+}
+
+fn add_source(sources: &mut Vec<(GerritUser, Vec<String>)>, user: GerritUser, source: Option<String>) {
+    match sources.iter_mut().find(|(u, _)| u.same_account(&user)) {
+        Some((_, s)) => {
+            if let Some(source) = source {
+                if !s.contains(&source) {
+                    s.push(source);
+                }
+            }
+        },
+        None => sources.push((user, source.into_iter().collect())),
+    }
+}
+
+/// Voters on the branch, members of the configured groups and owners of the
+/// changed files, each with the groups and OWNERS naming the user.
+/// Voters come first, they have all of the account id, email and username
+/// to match users of groups and OWNERS by any of them.
+fn candidate_sources(change: &GerritChange, review: &Review, history: &[Review]) -> Vec<(GerritUser, Vec<String>)> {
+    let mut sources = Vec::new();
+
+    for r in history {
+        for a in approvals(r) {
+            add_source(&mut sources, users::get_git_user(&a["by"]), None);
+        }
+    }
+
+    let expander = GroupExpander::new(&change.conn, false);
+    for cfg in config::get_group_configs() {
+        for g in &cfg.groups {
+            match expander.expand(&g.name) {
+                Ok(expanded) => {
+                    for u in expanded.users {
+                        add_source(&mut sources, u, Some(format!("group {}", g.name)));
+                    }
+                },
+                Err(e) => warn!("{}", e),
+            }
+        }
+    }
+
+    // OWNERS of the target branch, the working tree may be another branch
+    let rev = match local_remote_of(change) {
+        Some(remote) => format!("{}/{}", remote, review["branch"].as_str().unwrap_or_default()),
+        None => {
+            info!("No remote of {} in this repository, skip OWNERS", change.conn.project());
+            return sources;
+        },
+    };
+    let read = |path: &Path| git::show_file(&rev, &path.to_string_lossy());
+    for f in review["currentPatchSet"]["files"].as_array().into_iter().flatten() {
+        let file = f["file"].as_str().unwrap_or_default();
+        // Magic files, e.g. /COMMIT_MSG
+        if file.starts_with('/') {
+            continue;
+        }
+        for (u, dir) in owners_of_file(read, file) {
+            add_source(&mut sources, u, Some(format!("OWNERS of {}", dir)));
+        }
+    }
+    sources
+}
+
+/// Remote of this repository which fetches the project of the change
+fn local_remote_of(change: &GerritChange) -> Option<String> {
+    git::get_remotes().into_iter().find(|r| match gerrit_if::get_remote_conn(None, r) {
+        Ok(conn) => conn.hostname() == change.conn.hostname() && conn.project() == change.conn.project(),
+        Err(_) => false,
+    })
+}
+
+fn approvals(review: &Review) -> impl Iterator<Item = &Value> {
+    review["patchSets"].as_array().into_iter().flatten()
+        .flat_map(|ps| ps["approvals"].as_array().into_iter().flatten())
+}
+
+fn vote_value(approval: &Value) -> i64 {
+    approval["value"].as_str().and_then(|v| v.parse().ok()).unwrap_or(0)
+}
+
+/// Why the user can give the vote, empty if nothing tells so, and on how
+/// many changes of the branch the user gave it
+fn evidence(user: &GerritUser, label: &str, needed: i64, review: &Review, history: &[Review], branch: &str) -> (Vec<String>, usize) {
+    let mut ret = Vec::new();
+    let permitted = review["permittedVotes"].as_array().into_iter().flatten()
+        .find(|p| p["type"] == label && users::get_git_user(&p["by"]).same_account(user))
+        .and_then(|p| p["max"].as_i64());
+    if let Some(max) = permitted {
+        if max >= needed {
+            ret.push(format!("may vote {:+}", max));
+        }
+    }
+    let given = history.iter()
+        .filter(|r| approvals(r).any(|a| a["type"] == label && vote_value(a) >= needed
+                                         && users::get_git_user(&a["by"]).same_account(user)))
+        .count();
+    if given > 0 {
+        ret.push(format!("gave {}{:+} on {} of {} merged changes of {}", label, needed, given, history.len(), branch));
+    }
+    (ret, given)
+}
+
+/// Requirements of the change, unsatisfied ones with who can satisfy them
+pub fn analyze(change: &GerritChange) -> Result<Vec<Gap>, String> {
+    let options = ["--current-patch-set", "--all-approvals", "--all-reviewers", "--submit-records", "--files"];
+    let reviews = gerrit_if::query_reviews(&change.conn, &options, &change.change_id)?;
+    let review = match reviews.first() {
+        Some(r) => r,
+        None => return Err(format!("Change {} not found", change.change_id)),
+    };
+    let requirements = parse_requirements(review);
+    if requirements.is_empty() {
+        return Err("No submit requirements reported by the server".to_string());
+    }
+
+    let unsatisfied = requirements.iter().any(|r| !r.satisfied && r.label.is_some());
+    let branch = review["branch"].as_str().unwrap_or_default().to_string();
+    let (history, sources, filter) = if unsatisfied {
+        let query = format!("status:merged branch:{}", ssh::quote_query_value(&branch));
+        let history = gerrit_if::query_all_reviews(&change.conn, &["--all-approvals"], &query, HISTORY_CHANGES)?;
+        let sources = candidate_sources(change, review, &history);
+        (history, sources, Some(CandidateFilter::new(change)?))
+    } else {
+        (Vec::new(), Vec::new(), None)
+    };
+    let owner = users::get_git_user(&review["owner"]);
+
+    let mut gaps = Vec::new();
+    for requirement in requirements {
+        let mut gap = Gap { needed: requirement.needed, requirement, reviewers: Vec::new(), candidates: Vec::new() };
+        let (label, filter) = match (&gap.requirement.label, &filter) {
+            (Some(l), Some(f)) if !gap.requirement.satisfied => (l.clone(), f),
+            _ => {
+                gaps.push(gap);
+                continue;
+            },
+        };
+        // MAX is the highest value of the label, else the highest seen on the branch
+        let needed = gap.needed
+            .or_else(|| review["labelMax"][&label].as_i64())
+            .or_else(|| history.iter().flat_map(approvals).filter(|a| a["type"] == label.as_str()).map(vote_value).max())
+            .unwrap_or(1);
+        gap.needed = Some(needed);
+
+        for r in review["allReviewers"].as_array().into_iter().flatten() {
+            let user = users::get_git_user(r);
+            if user == owner {
+                continue;
+            }
+            let (reasons, _) = evidence(&user, &label, needed, review, &history, &branch);
+            if !reasons.is_empty() {
+                let mut row = UserRow::new(user, "REVIEWER");
+                row.reasons = reasons;
+                gap.reviewers.push(row);
+            }
+        }
+        for (user, from) in &sources {
+            if filter.check(user).is_some() {
+                continue;
+            }
+            let (reasons, given) = evidence(user, &label, needed, review, &history, &branch);
+            if reasons.is_empty() {
+                continue;
+            }
+            let mut row = UserRow::new(user.clone(), "CANDIDATE");
+            row.score = Some(given as i64);
+            row.reasons = from.clone();
+            row.reasons.extend(reasons);
+            gap.candidates.push(Candidate { row, named_by: from.clone() });
+        }
+        // Users named by groups or OWNERS first, then the most active
+        gap.candidates.sort_by_key(|c| std::cmp::Reverse((!c.named_by.is_empty(), c.row.score)));
+        gaps.push(gap);
+    }
+    Ok(gaps)
+}
+
+impl Gap {
+    /// e.g. "needs Code-Review+2"
+    pub fn describe_need(&self) -> String {
+        match (&self.requirement.label, self.needed) {
+            (Some(l), Some(n)) => format!("needs {}{:+}", l, n),
+            (Some(l), None) => format!("needs {}", l),
+            (None, _) => "not satisfied by a vote".to_string(),
+        }
+    }
+}
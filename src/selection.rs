@@ -0,0 +1,176 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::{trace, debug, warn};
+use clap::ValueEnum;
+use rand::seq::SliceRandom;
+use serde_json::{json, Value};
+
+use crate::config;
+use crate::plan;
+use crate::gerrit_if;
+use crate::gerrit_if::GitUrl;
+use crate::groups::{GerritUserGroup, GroupExpander};
+use crate::candidates::{CandidateFilter, Skipped};
+use crate::availability::Availability;
+use crate::users::GerritUser;
+use crate::pool;
+
+const HISTORY_NAME: &str = "gerreviewer-assignments.json";
+
+/// How reviewers are chosen from the members of a group
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum)]
+pub enum SelectMode {
+    /// Every member
+    All,
+    /// Members picked at random
+    Random,
+    /// Members who were assigned the longest time ago
+    RoundRobin,
+    /// Members with the fewest open reviews
+    LeastLoaded,
+}
+
+impl SelectMode {
+    pub fn parse(s: &str) -> Option<SelectMode> {
+        SelectMode::from_str(s, true).ok()
+    }
+
+    pub fn name(&self) -> String {
+        self.to_possible_value().unwrap().get_name().to_string()
+    }
+}
+
+/// Past assignments of group members, kept in the git directory
+/// as `{ group: { username: { "last": secs, "count": n } } }`
+pub struct AssignmentHistory {
+    file_path: Option<String>,
+    groups: Value,
+}
+
+impl Default for AssignmentHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AssignmentHistory {
+    pub fn new() -> Self {
+        let file_path = config::get_data_path(HISTORY_NAME);
+        let groups = match &file_path {
+            Some(p) if Path::new(p).exists() => {
+                let contents = fs::read_to_string(p).unwrap_or_default();
+                serde_json::from_str(&contents).unwrap_or_else(|e| {
+                    warn!("Ignore invalid {}: {}", p, e);
+                    json!({})
+                })
+            },
+            _ => json!({}),
+        };
+        Self { file_path, groups }
+    }
+
+    fn entry(&self, group: &str, user: &GerritUser) -> &Value {
+        &self.groups[group][&user.account()]
+    }
+
+    pub fn last_assigned(&self, group: &str, user: &GerritUser) -> u64 {
+        self.entry(group, user)["last"].as_u64().unwrap_or(0)
+    }
+
+    pub fn assigned_count(&self, group: &str, user: &GerritUser) -> u64 {
+        self.entry(group, user)["count"].as_u64().unwrap_or(0)
+    }
+
+    pub fn record(&mut self, group: &str, users: &[GerritUser]) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        for u in users {
+            let count = self.assigned_count(group, u) + 1;
+            if !self.groups[group].is_object() {
+                self.groups[group] = json!({});
+            }
+            self.groups[group][&u.account()] = json!({ "last": now, "count": count });
+        }
+    }
+
+    pub fn save(&self) -> Result<(), std::io::Error> {
+        let path = match &self.file_path {
+            Some(p) if !plan::is_dry_run() => p,
+            _ => return Ok(()),
+        };
+        let mut file = File::create(path)?;
+        file.write_all(self.groups.to_string().as_bytes())?;
+        trace!("Saved {}", path);
+        Ok(())
+    }
+}
+
+/// Choose reviewers among the expanded users of the group according to its mode
+pub fn select(group: &GerritUserGroup, mut users: Vec<GerritUser>, conn: &GitUrl, history: &AssignmentHistory) -> Result<Vec<GerritUser>, String> {
+    let pick = group.pick.min(users.len());
+
+    match group.mode {
+        SelectMode::All => return Ok(users),
+        SelectMode::Random => {
+            users.shuffle(&mut rand::thread_rng());
+        },
+        SelectMode::RoundRobin => {
+            // Stable sort keeps the group order among never assigned members
+            users.sort_by_key(|u| history.last_assigned(&group.name, u));
+        },
+        SelectMode::LeastLoaded => {
+            let counts = pool::map(users, pool::jobs(), |u| {
+                let count = gerrit_if::count_open_reviews(conn, &u);
+                (u, count)
+            });
+            let mut loads = Vec::new();
+            for (u, count) in counts {
+                // Local assignment counts are not comparable to open reviews
+                let load = count.map_err(|e| format!("Failed to query open reviews of {}: {}", u, e))?;
+                debug!("{} has {} open reviews", u, load);
+                loads.push((load, history.last_assigned(&group.name, &u), u));
+            }
+            loads.sort_by_key(|(load, last, _)| (*load, *last));
+            users = loads.into_iter().map(|(_, _, u)| u).collect();
+        },
+    }
+
+    users.truncate(pick);
+    Ok(users)
+}
+
+/// Reviewers chosen from a group, the Gerrit groups Gerrit expands itself,
+/// and the members left out with the reason
+#[derive(Debug)]
+pub struct GroupPick {
+    pub users: Vec<GerritUser>,
+    pub groups: Vec<String>,
+    pub skipped: Vec<Skipped>,
+}
+
+/// Expand the group and choose reviewers among the members who are candidates and available
+pub fn pick_from_group(name: &str, conn: &GitUrl, filter: &CandidateFilter, history: &AssignmentHistory) -> Result<GroupPick, String> {
+    let group = match GroupExpander::new(conn, false).find(name) {
+        Some(g) => g.clone(),
+        None => return Err(format!("Group {} not found", name)),
+    };
+    // Members are chosen one by one, so Gerrit groups are expanded into their
+    // members instead of being added as a whole
+    let expanded = GroupExpander::new(conn, group.mode != SelectMode::All).expand(name)?;
+    let groups = expanded.gerrit_groups;
+    if expanded.users.is_empty() && groups.is_empty() {
+        return Err(format!("Group {} has no members", name));
+    }
+
+    let (users, mut skipped) = filter.partition(expanded.users.into_iter().collect());
+    let (users, away) = Availability::new(conn).partition(users);
+    skipped.extend(away);
+    if users.is_empty() && groups.is_empty() {
+        let reasons: Vec<String> = skipped.iter().map(|s| s.describe()).collect();
+        return Err(format!("No available members in group {}:\n{}", name, reasons.join("\n")));
+    }
+
+    let users = select(&group, users, conn, history)?;
+    Ok(GroupPick { users, groups, skipped })
+}
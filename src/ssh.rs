@@ -0,0 +1,180 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use regex::Regex;
+use log::warn;
+
+use crate::os::{CommandRunner, CommandError};
+use crate::plan;
+use crate::config;
+use crate::gerrit_if::GitUrl;
+
+const DEFAULT_CONTROL_PERSIST: &str = "10m";
+
+/// Options of the local ssh process, read once from the `gerreviewer.ssh*` settings
+pub struct SshOptions {
+    pub program: String,
+    pub identity: Option<String>,
+    pub proxy_jump: Option<String>,
+    /// Raw `-o` options, e.g. `StrictHostKeyChecking=yes`
+    pub options: Vec<String>,
+    /// Directory of the ControlMaster sockets, None if connection reuse is off
+    pub control_dir: Option<PathBuf>,
+    pub control_persist: String,
+}
+
+impl SshOptions {
+    pub fn load() -> Self {
+        let control = match config::get_setting("sshControlMaster") {
+            Some(v) => matches!(v.as_str(), "true" | "yes" | "on" | "1"),
+            None => false,
+        };
+        Self {
+            program: config::get_setting("sshCommand").unwrap_or_else(|| "ssh".to_string()),
+            identity: config::get_setting("sshIdentity"),
+            proxy_jump: config::get_setting("sshProxyJump"),
+            options: config::get_settings("sshOption"),
+            control_dir: if control { Some(control_dir()) } else { None },
+            control_persist: config::get_setting("sshControlPersist")
+                .unwrap_or_else(|| DEFAULT_CONTROL_PERSIST.to_string()),
+        }
+    }
+
+    fn to_argv(&self) -> Vec<String> {
+        let mut argv = vec![self.program.clone(), "-x".to_string()];
+        if let Some(i) = &self.identity {
+            argv.push("-i".to_string());
+            argv.push(i.clone());
+        }
+        if let Some(j) = &self.proxy_jump {
+            argv.push("-J".to_string());
+            argv.push(j.clone());
+        }
+        if let Some(dir) = &self.control_dir {
+            // %C is a hash of the connection, short enough for a socket path
+            argv.push("-o".to_string());
+            argv.push("ControlMaster=auto".to_string());
+            argv.push("-o".to_string());
+            argv.push(format!("ControlPath={}", dir.join("%C").display()));
+            argv.push("-o".to_string());
+            argv.push(format!("ControlPersist={}", self.control_persist));
+        }
+        for o in &self.options {
+            argv.push("-o".to_string());
+            argv.push(o.clone());
+        }
+        argv
+    }
+}
+
+static SSH_OPTIONS: OnceLock<SshOptions> = OnceLock::new();
+
+pub fn options() -> &'static SshOptions {
+    SSH_OPTIONS.get_or_init(SshOptions::load)
+}
+
+// Sockets are kept under the runtime directory which only the user can access
+fn control_dir() -> PathBuf {
+    let base = dirs::runtime_dir().unwrap_or_else(std::env::temp_dir);
+    let dir = base.join("gerreviewer-ssh");
+    if let Err(e) = fs::create_dir_all(&dir) {
+        warn!("Failed to create {}: {}", dir.display(), e);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = fs::set_permissions(&dir, fs::Permissions::from_mode(0o700));
+    }
+    dir
+}
+
+/// Command run on the Gerrit server over SSH, e.g. `gerrit query`.
+///
+/// SSH joins the remote arguments with spaces and Gerrit splits them again,
+/// so every remote argument is quoted to reach Gerrit unchanged.
+pub struct SshCommand {
+    destination: String,
+    port: Option<u16>,
+    remote_args: Vec<String>,
+}
+
+impl SshCommand {
+    pub fn new(url: &GitUrl, command: &str) -> Self {
+        let destination = match &url.username {
+            Some(s) => format!("{}@{}", s, url.hostname()),
+            None => url.hostname().to_string(),
+        };
+        Self {
+            destination,
+            port: url.port(),
+            remote_args: vec!["gerrit".to_string(), command.to_string()],
+        }
+    }
+
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.remote_args.push(arg.to_string());
+        self
+    }
+
+    pub fn args(mut self, args: &[&str]) -> Self {
+        for a in args {
+            self.remote_args.push(a.to_string());
+        }
+        self
+    }
+
+    /// Arguments of the local ssh process
+    pub fn to_argv(&self) -> Vec<String> {
+        let mut argv = options().to_argv();
+        if let Some(p) = self.port {
+            argv.push("-p".to_string());
+            argv.push(p.to_string());
+        }
+        // Nothing after this is taken as an ssh option
+        argv.push("--".to_string());
+        argv.push(self.destination.clone());
+        for a in &self.remote_args {
+            argv.push(quote_remote_arg(a));
+        }
+        argv
+    }
+
+    pub fn run(&self) -> Result<String, String> {
+        let argv = self.to_argv();
+        let network = config::get_network_settings();
+        // ssh exits with 255 when the connection fails
+        CommandRunner::new(&argv.iter().map(|s| s.as_str()).collect::<Vec<&str>>())
+            .timeout(network.timeout)
+            .retries(network.retries, |e| matches!(e, CommandError::Timeout(_)) || e.code() == Some(255))
+            .run()
+    }
+
+    /// Run a command which changes the state of Gerrit, only return it in dry-run mode
+    pub fn run_mutation(&self) -> Result<String, String> {
+        if plan::is_dry_run() {
+            return Ok(format!("Would run: {}", self.to_argv().join(" ")));
+        }
+        self.run()
+    }
+}
+
+/// Quote the argument for the command line parser of the Gerrit SSH daemon
+pub fn quote_remote_arg(arg: &str) -> String {
+    let safe = Regex::new(r"^[A-Za-z0-9_@%+=:,./~-]+$").unwrap();
+    if safe.is_match(arg) {
+        return arg.to_string();
+    }
+    if !arg.contains('\'') {
+        return format!("'{}'", arg);
+    }
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Quote a value inside a Gerrit search query, e.g. the name of a topic
+pub fn quote_query_value(value: &str) -> String {
+    let safe = Regex::new(r"^[A-Za-z0-9_@.+/~-]+$").unwrap();
+    if safe.is_match(value) {
+        return value.to_string();
+    }
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
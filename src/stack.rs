@@ -0,0 +1,136 @@
+use log::info;
+use linked_hash_set::LinkedHashSet;
+
+use crate::git;
+use crate::gerrit_if;
+use crate::gerrit_if::{GerritChange, GitUrl};
+use crate::users::{GerritUser, insert_user};
+use crate::candidates::CandidateFilter;
+use crate::selection;
+use crate::plan::ReviewerPlan;
+
+pub struct StackRequest {
+    pub upstream: String,
+    /// Use Gerrit related changes instead of local commits
+    pub related: bool,
+    pub remote: String,
+    pub add: Vec<String>,
+    pub remove: Vec<String>,
+    pub groups: Vec<String>,
+}
+
+pub struct StackChange {
+    pub change: GerritChange,
+    pub subject: String,
+}
+
+/// Changes of the stack and the result of the reviewer changes on each
+pub struct StackOutcome {
+    pub stack: Vec<StackChange>,
+    /// Told before the results, e.g. who is skipped
+    pub notes: Vec<String>,
+    /// Result on each change in the order of the stack, empty when nothing is changed
+    pub results: Vec<Result<String, String>>,
+    /// Reviewers of each change once the results are in, in the order of the stack
+    pub reviewers: Vec<Vec<GerritUser>>,
+}
+
+pub fn get_stack(req: &StackRequest, conn: &GitUrl, change_id: Option<&str>) -> Result<Vec<StackChange>, String> {
+    let mut stack = Vec::new();
+    if req.related {
+        let change_id = match change_id {
+            Some(id) => id.to_string(),
+            None => git::get_local_commit_change_id("HEAD").unwrap_or_default(),
+        };
+        if change_id.is_empty() {
+            return Err("No change to find related changes of, use --change".to_string());
+        }
+        let change = GerritChange { conn: conn.clone(), change_id };
+        for id in gerrit_if::get_related_change_ids(&change)? {
+            stack.push(StackChange {
+                change: GerritChange { conn: conn.clone(), change_id: id },
+                subject: String::new(),
+            });
+        }
+    } else {
+        for c in git::get_local_changes(&req.upstream)? {
+            let change = GerritChange { conn: conn.clone(), change_id: c.change_id };
+            if !gerrit_if::is_change_open(&change)? {
+                info!("Skip {} ({}) which is not open", change.change_id, c.sha);
+                continue;
+            }
+            stack.push(StackChange { change, subject: c.subject });
+        }
+    }
+    Ok(stack)
+}
+
+fn change_reviewers(change: &GerritChange, adds: &[GerritUser], groups: &[String], removes: &[GerritUser]) -> Result<String, String> {
+    let mut msgs = Vec::new();
+    if !adds.is_empty() || !groups.is_empty() {
+        let mut plan = ReviewerPlan::new(change);
+        plan.adds = adds.to_vec();
+        plan.groups = groups.to_vec();
+        msgs.push(gerrit_if::apply_plan(&plan)?);
+    }
+    if !removes.is_empty() {
+        msgs.push(gerrit_if::delete_reviewers(change, removes)?);
+    }
+    Ok(msgs.join("\n"))
+}
+
+/// Apply the same reviewer changes to every change of the stack
+pub fn run(req: &StackRequest, url: Option<&str>, change_id: Option<&str>) -> Result<StackOutcome, String> {
+    let conn = gerrit_if::get_remote_conn(url, &req.remote)?;
+    let stack = get_stack(req, &conn, change_id)?;
+    if stack.is_empty() {
+        return Err("No open changes in the stack".to_string());
+    }
+
+    let owner = conn.username.as_ref().map(|u| GerritUser::from_str(u));
+    let filter = CandidateFilter::for_owner(owner);
+
+    let mut notes = Vec::new();
+    let mut adds = LinkedHashSet::new();
+    let (named, skipped) = filter.partition(req.add.iter().map(|u| GerritUser::from_str(u)).collect());
+    notes.extend(skipped.iter().map(|s| s.describe()));
+    for u in named {
+        insert_user(&mut adds, u);
+    }
+    let mut groups = LinkedHashSet::new();
+    let mut history = selection::AssignmentHistory::new();
+    let mut picked = Vec::new();
+    for g in &req.groups {
+        let pick = selection::pick_from_group(g, &conn, &filter, &history)?;
+        notes.extend(pick.skipped.iter().map(|s| s.describe()));
+        for u in &pick.users {
+            insert_user(&mut adds, u.clone());
+        }
+        for name in pick.groups {
+            groups.insert_if_absent(name);
+        }
+        picked.push((g, pick.users));
+    }
+    let adds: Vec<GerritUser> = adds.into_iter().collect();
+    let groups: Vec<String> = groups.into_iter().collect();
+    let removes: Vec<GerritUser> = req.remove.iter().map(|u| GerritUser::from_str(u)).collect();
+
+    let mut results = Vec::new();
+    if !adds.is_empty() || !groups.is_empty() || !removes.is_empty() {
+        for c in &stack {
+            results.push(change_reviewers(&c.change, &adds, &groups, &removes));
+        }
+
+        for (g, users) in picked {
+            history.record(g, &users);
+        }
+        if let Err(err) = history.save() {
+            notes.push(format!("Failed to save assignment history: {}", err));
+        }
+    }
+
+    let reviewers = stack.iter()
+        .map(|c| gerrit_if::get_reviewers(&c.change))
+        .collect::<Result<_, _>>()?;
+    Ok(StackOutcome { stack, notes, results, reviewers })
+}
@@ -0,0 +1,204 @@
+use chrono::{Duration, Local, NaiveDate};
+use serde_json::Value;
+
+use crate::gerrit_if;
+use crate::ssh;
+use crate::users;
+use crate::users::GerritUser;
+
+use serde_json::Value as Review;
+
+const DEFAULT_DAYS: i64 = 30;
+
+pub struct StatsRequest {
+    /// First day of the range, default to 30 days ago
+    pub since: Option<String>,
+    /// Last day of the range, inclusive
+    pub until: Option<String>,
+    pub project: Option<String>,
+    pub branch: Option<String>,
+    pub remote: String,
+    /// Stop after this many changes
+    pub max: usize,
+}
+
+/// Review activity of one user on changes of others
+pub struct ReviewerStats {
+    pub user: GerritUser,
+    /// Changes the user is a reviewer of
+    pub reviews: usize,
+    /// Non-zero votes on any label and patch set
+    pub votes: usize,
+    /// Seconds from the creation of each change to the first comment or vote of the user
+    first_reviews: Vec<i64>,
+    /// Open changes the user has not voted on yet
+    pub open_queue: usize,
+}
+
+impl ReviewerStats {
+    fn new(user: GerritUser) -> Self {
+        Self {
+            user,
+            reviews: 0,
+            votes: 0,
+            first_reviews: Vec::new(),
+            open_queue: 0,
+        }
+    }
+
+    pub fn median_first_review(&self) -> Option<i64> {
+        let mut secs = self.first_reviews.clone();
+        if secs.is_empty() {
+            return None;
+        }
+        secs.sort();
+        let mid = secs.len() / 2;
+        if secs.len().is_multiple_of(2) {
+            Some((secs[mid - 1] + secs[mid]) / 2)
+        } else {
+            Some(secs[mid])
+        }
+    }
+}
+
+fn parse_date(s: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|_| format!("Invalid date {}, expected YYYY-MM-DD", s))
+}
+
+fn entry<'a>(stats: &'a mut Vec<ReviewerStats>, user: &GerritUser) -> &'a mut ReviewerStats {
+    let pos = match stats.iter().position(|s| s.user == *user) {
+        Some(pos) => pos,
+        None => {
+            stats.push(ReviewerStats::new(user.clone()));
+            stats.len() - 1
+        },
+    };
+    &mut stats[pos]
+}
+
+fn record_first(firsts: &mut Vec<(GerritUser, i64)>, user: &GerritUser, time: Option<i64>) {
+    let time = match time {
+        Some(t) => t,
+        None => return,
+    };
+    match firsts.iter_mut().find(|(u, _)| u == user) {
+        Some((_, t)) => *t = (*t).min(time),
+        None => firsts.push((user.clone(), time)),
+    }
+}
+
+fn approvals(review: &Review) -> impl Iterator<Item = &Value> {
+    review["patchSets"].as_array().into_iter().flatten()
+        .flat_map(|ps| ps["approvals"].as_array().into_iter().flatten())
+}
+
+/// Per reviewer statistics of the changes, most active reviewers first.
+/// Reviews of own changes are not counted.
+pub fn collect(reviews: &[Review]) -> Vec<ReviewerStats> {
+    let mut stats: Vec<ReviewerStats> = Vec::new();
+    for r in reviews {
+        let owner = users::get_git_user(&r["owner"]);
+        let created = r["createdOn"].as_i64();
+        let open = r["open"].as_bool().unwrap_or(false);
+
+        // First comment or vote of each user on the change
+        let mut firsts: Vec<(GerritUser, i64)> = Vec::new();
+        let mut voters: Vec<GerritUser> = Vec::new();
+        for a in approvals(r) {
+            let by = users::get_git_user(&a["by"]);
+            let value = a["value"].as_str().and_then(|v| v.parse::<i64>().ok()).unwrap_or(0);
+            if by == owner || value == 0 {
+                continue;
+            }
+            entry(&mut stats, &by).votes += 1;
+            record_first(&mut firsts, &by, a["grantedOn"].as_i64());
+            if !voters.contains(&by) {
+                voters.push(by);
+            }
+        }
+        for c in r["comments"].as_array().into_iter().flatten() {
+            let author = users::get_git_user(&c["reviewer"]);
+            if author != owner {
+                record_first(&mut firsts, &author, c["timestamp"].as_i64());
+            }
+        }
+
+        for reviewer in r["allReviewers"].as_array().into_iter().flatten() {
+            let user = users::get_git_user(reviewer);
+            if user == owner {
+                continue;
+            }
+            let s = entry(&mut stats, &user);
+            s.reviews += 1;
+            if open && !voters.contains(&user) {
+                s.open_queue += 1;
+            }
+            let first = firsts.iter().find(|(u, _)| *u == user).map(|(_, t)| *t);
+            if let (Some(created), Some(first)) = (created, first) {
+                s.first_reviews.push((first - created).max(0));
+            }
+        }
+    }
+    stats.sort_by(|a, b| b.reviews.cmp(&a.reviews).then(b.votes.cmp(&a.votes)));
+    stats
+}
+
+/// e.g. "2d 3h", "45m"
+pub fn format_duration(secs: i64) -> String {
+    let d = Duration::seconds(secs);
+    if d.num_days() > 0 {
+        format!("{}d {}h", d.num_days(), d.num_hours() % 24)
+    } else if d.num_hours() > 0 {
+        format!("{}h {}m", d.num_hours(), d.num_minutes() % 60)
+    } else {
+        format!("{}m", d.num_minutes())
+    }
+}
+
+/// Reviewer statistics of the changes of a project updated in a date range
+pub struct StatsReport {
+    pub project: String,
+    /// e.g. "2024-06-01 to 2024-06-30" or "since 2024-06-01"
+    pub range: String,
+    /// Number of changes mined
+    pub changes: usize,
+    pub stats: Vec<ReviewerStats>,
+}
+
+/// Mine changes updated in the date range for reviewer statistics
+pub fn run(req: &StatsRequest, url: Option<&str>) -> Result<StatsReport, String> {
+    let mut conn = gerrit_if::get_remote_conn(url, &req.remote)?;
+    if let Some(p) = &req.project {
+        conn = conn.with_project(p);
+    }
+    let since = match &req.since {
+        Some(s) => parse_date(s)?,
+        None => Local::now().date_naive() - Duration::days(DEFAULT_DAYS),
+    };
+    let until = req.until.as_deref().map(parse_date).transpose()?;
+    if until.is_some_and(|u| u < since) {
+        return Err("The range ends before it starts".to_string());
+    }
+
+    let mut query = vec![format!("after:{}", since)];
+    if let Some(u) = until {
+        // before: is exclusive
+        query.push(format!("before:{}", u + Duration::days(1)));
+    }
+    if let Some(b) = &req.branch {
+        query.push(format!("branch:{}", ssh::quote_query_value(b)));
+    }
+    let reviews = gerrit_if::query_all_reviews(&conn, &["--all-reviewers", "--all-approvals", "--comments"],
+                                               &query.join(" "), req.max)?;
+
+    let range = match until {
+        Some(u) => format!("{} to {}", since, u),
+        None => format!("since {}", since),
+    };
+    Ok(StatsReport {
+        project: conn.project().to_string(),
+        range,
+        changes: reviews.len(),
+        stats: collect(&reviews),
+    })
+}
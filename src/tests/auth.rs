@@ -0,0 +1,55 @@
+use crate::auth::{Secret, parse_gitcookies, parse_netrc};
+
+fn basic(secret: Option<Secret>) -> Option<(String, String)> {
+    match secret? {
+        Secret::Basic { username, password } => Some((username, password)),
+        Secret::Cookie(_) => None,
+    }
+}
+
+fn cookie(secret: Option<Secret>) -> Option<String> {
+    match secret? {
+        Secret::Cookie(c) => Some(c),
+        Secret::Basic { .. } => None,
+    }
+}
+
+fn pair(username: &str, password: &str) -> Option<(String, String)> {
+    Some((username.to_string(), password.to_string()))
+}
+
+#[test]
+fn netrc_machine_or_default() {
+    let netrc = "\
+machine other.example.com login eve password secret1
+machine gerrit.example.com
+    login bob
+    account ignored
+    password secret2
+default login anon password secret3
+";
+    assert_eq!(basic(parse_netrc(netrc, "gerrit.example.com")), pair("bob", "secret2"));
+    assert_eq!(basic(parse_netrc(netrc, "other.example.com")), pair("eve", "secret1"));
+    assert_eq!(basic(parse_netrc(netrc, "unknown.example.com")), pair("anon", "secret3"));
+
+    // A machine entry wins over the default before it, and needs both fields
+    let netrc = "default login anon password secret3\nmachine gerrit.example.com login bob password secret2";
+    assert_eq!(basic(parse_netrc(netrc, "gerrit.example.com")), pair("bob", "secret2"));
+    assert_eq!(basic(parse_netrc("machine gerrit.example.com login bob", "gerrit.example.com")), None);
+    assert_eq!(basic(parse_netrc("", "gerrit.example.com")), None);
+}
+
+#[test]
+fn gitcookies_of_host_and_domain() {
+    let cookies = [
+        "# Netscape HTTP Cookie File",
+        "gerrit.example.com\tFALSE\t/\tTRUE\t2147483647\to\tgit-bob=abc",
+        "#HttpOnly_.example.com\tTRUE\t/\tTRUE\t2147483647\tsession\txyz",
+        "other.example.com\tFALSE\t/\tTRUE\t2147483647\to\tgit-eve=def",
+        "malformed line",
+    ].join("\n");
+    assert_eq!(cookie(parse_gitcookies(&cookies, "gerrit.example.com")).as_deref(), Some("o=git-bob=abc; session=xyz"));
+    assert_eq!(cookie(parse_gitcookies(&cookies, "review.example.com")).as_deref(), Some("session=xyz"));
+    assert_eq!(cookie(parse_gitcookies(&cookies, "example.com")).as_deref(), Some("session=xyz"));
+    assert_eq!(cookie(parse_gitcookies(&cookies, "gerrit.example.org")), None);
+}
@@ -0,0 +1,44 @@
+use crate::availability::Availability;
+use crate::users::GerritUser;
+
+use super::harness::{FakeGerrit, SSH_URL, change};
+
+#[test]
+fn away_dates_and_ranges() {
+    let away = [
+        "alice",
+        "bob ..2999-12-31",
+        "carol 2000-01-01..2000-01-02",
+        "dave 2999-01-01..",
+        "erin 2000-01-01..",
+        // Invalid entries are ignored
+        "frank 2000-01-01",
+        "grace 2000-13-01..",
+    ];
+    let _env = FakeGerrit::new().setting("gerreviewer.away", &away.join("\n")).install();
+
+    let conn = change(SSH_URL, "I123").conn;
+    let availability = Availability::new(&conn);
+    let check = |name: &str| availability.check(&GerritUser::from_str(name));
+    assert_eq!(check("alice").as_deref(), Some("away"));
+    assert_eq!(check("bob").as_deref(), Some("away until 2999-12-31"));
+    assert_eq!(check("carol"), None);
+    assert_eq!(check("dave"), None);
+    assert_eq!(check("erin").as_deref(), Some("away"));
+    assert_eq!(check("frank"), None);
+    assert_eq!(check("grace"), None);
+    assert_eq!(check("heidi"), None);
+}
+
+#[test]
+fn partition_tells_who_is_away() {
+    let _env = FakeGerrit::new().setting("gerreviewer.away", "bob ..2999-12-31").install();
+
+    let conn = change(SSH_URL, "I123").conn;
+    let users = ["alice", "bob", "carol"].iter().map(|u| GerritUser::from_str(u)).collect();
+    let (kept, skipped) = Availability::new(&conn).partition(users);
+    let kept: Vec<String> = kept.iter().map(|u| u.account()).collect();
+    assert_eq!(kept, ["alice", "carol"]);
+    let skipped: Vec<String> = skipped.iter().map(|s| s.describe()).collect();
+    assert_eq!(skipped, ["Skip bob: away until 2999-12-31"]);
+}
@@ -0,0 +1,35 @@
+use crate::candidates::CandidateFilter;
+use crate::users::{GerritUser, UserIdentifier};
+
+use super::harness::FakeGerrit;
+
+fn user(username: &str, email: &str) -> GerritUser {
+    GerritUser { email: email.to_string(), ..GerritUser::from_str(username) }
+}
+
+#[test]
+fn bots_by_username_or_email_local_part() {
+    let _env = FakeGerrit::new().install();
+    let filter = CandidateFilter::for_owner(None);
+    let service = Some("service account".to_string());
+
+    assert_eq!(filter.check(&user("ci-bot", "")), service);
+    assert_eq!(filter.check(&user("builder", "jenkins.runner@example.com")), service);
+    assert_eq!(filter.check(&UserIdentifier::parse("zuul@example.com").unwrap().to_user()), service);
+
+    // Domains of real users are not service accounts
+    assert_eq!(filter.check(&user("alice", "alice@ci.example.com")), None);
+    assert_eq!(filter.check(&UserIdentifier::parse("bob@service.corp").unwrap().to_user()), None);
+    assert_eq!(filter.check(&user("robotics", "")), None);
+}
+
+#[test]
+fn owner_and_blocked_users_are_not_candidates() {
+    let _env = FakeGerrit::new().setting("gerreviewer.blocked", "carol@example.com").install();
+    let filter = CandidateFilter::for_owner(Some(GerritUser::from_str("bob")));
+
+    let bob = GerritUser { account_id: Some(1000), ..user("bob", "bob@example.com") };
+    assert_eq!(filter.check(&bob), Some("owner of the change".to_string()));
+    assert_eq!(filter.check(&user("carol", "carol@example.com")), Some("blocked".to_string()));
+    assert_eq!(filter.check(&user("dave", "dave@example.com")), None);
+}
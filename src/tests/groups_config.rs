@@ -0,0 +1,128 @@
+use std::fs;
+
+use crate::config;
+use crate::config::{ConfigScope, GroupsConfig};
+use crate::groups::{GerritUserGroup, GroupExpander, GroupRef};
+use crate::users::{GerritUser, UserIdentifier};
+
+use super::harness::{FakeGerrit, SSH_URL, change};
+
+const MEMBERS: &[&str] = &[
+    "Jörg Müller <jörg+ci@example.com>",
+    "Doe, John <john@example.com>",
+    "Ann \"Annie\" Lee <ann@example.com>",
+    "<noname@example.com>",
+    "1000042",
+    "zoe.w",
+    "José María <jose@example.com>",
+    "a.b+c@example.org",
+];
+
+fn user(s: &str) -> GerritUser {
+    UserIdentifier::parse(s).unwrap_or_else(|| panic!("Invalid identifier: {}", s)).to_user()
+}
+
+#[test]
+fn parse_identifiers() {
+    assert_eq!(UserIdentifier::parse("1000042"), Some(UserIdentifier::AccountId(1000042)));
+    assert_eq!(UserIdentifier::parse("zoe.w"), Some(UserIdentifier::Username("zoe.w".to_string())));
+    assert_eq!(UserIdentifier::parse("a.b+c@example.org"), Some(UserIdentifier::Email("a.b+c@example.org".to_string())));
+    assert_eq!(UserIdentifier::parse("Doe, John <john@example.com>"),
+               Some(UserIdentifier::NameEmail { name: "Doe, John".to_string(), email: "john@example.com".to_string() }));
+
+    for invalid in ["", "bob,alice", "a<b", "Name <not an email>", "tab\there", "zoë", "José María"] {
+        assert_eq!(UserIdentifier::parse(invalid), None, "{:?}", invalid);
+    }
+}
+
+#[test]
+fn quote_and_split_members() {
+    assert_eq!(config::quote_member("jörg+ci@example.com"), "jörg+ci@example.com");
+    assert_eq!(config::quote_member("gerrit:Core"), "gerrit:Core");
+    assert_eq!(config::quote_member("Doe, John <john@example.com>"), "\"Doe, John <john@example.com>\"");
+    assert_eq!(config::quote_member("a \"b\" \\c"), "\"a \\\"b\\\" \\\\c\"");
+
+    let line = MEMBERS.iter().map(|m| config::quote_member(m)).collect::<Vec<_>>().join(", ");
+    assert_eq!(config::split_members(&line), MEMBERS);
+}
+
+#[test]
+fn groups_round_trip() {
+    let fake = FakeGerrit::new().install();
+
+    let mut cfg = GroupsConfig::new(ConfigScope::Local);
+    let mut group = GerritUserGroup::new("core");
+    for m in MEMBERS {
+        group.users.insert(user(m));
+    }
+    group.includes.insert(GroupRef::parse("gerrit:My Group").unwrap());
+    group.includes.insert(GroupRef::parse("@infra").unwrap());
+    cfg.insert_group(group).unwrap();
+    cfg.save().unwrap();
+
+    let contents = fs::read_to_string(fake.data_file("gerreviewer.ini")).unwrap();
+    assert!(contents.contains("\"Doe, John <john@example.com>\""), "{}", contents);
+
+    let loaded = GroupsConfig::new(ConfigScope::Local);
+    let group = loaded.get("core").expect("Group not loaded");
+    let users: Vec<&GerritUser> = group.users.iter().collect();
+    assert_eq!(users.len(), MEMBERS.len());
+    for (u, m) in users.iter().zip(MEMBERS) {
+        let expected = user(m);
+        assert_eq!(u.username, expected.username);
+        assert_eq!(u.email, expected.email);
+        assert_eq!(u.full_name, expected.full_name);
+        assert_eq!(u.account_id, expected.account_id);
+    }
+    let includes: Vec<String> = group.includes.iter().map(|r| r.to_string()).collect();
+    assert_eq!(includes, ["gerrit:My Group", "@infra"]);
+}
+
+// Each group with its users and the local groups it includes
+fn save_groups(groups: &[(&str, &[&str], &[&str])]) {
+    let mut cfg = GroupsConfig::new(ConfigScope::Local);
+    for (name, users, includes) in groups {
+        let mut group = GerritUserGroup::new(name);
+        for u in *users {
+            group.users.insert(GerritUser::from_str(u));
+        }
+        for g in *includes {
+            group.includes.insert(GroupRef::Local(g.to_string()));
+        }
+        cfg.insert_group(group).unwrap();
+    }
+    cfg.save().unwrap();
+}
+
+#[test]
+fn nested_groups_are_expanded_once() {
+    let _env = FakeGerrit::new().install();
+    save_groups(&[
+        ("all", &["alice"], &["web", "infra"]),
+        ("web", &["bob"], &["core"]),
+        ("infra", &["carol"], &["core"]),
+        ("core", &["dave", "alice"], &[]),
+    ]);
+
+    let conn = change(SSH_URL, "I123").conn;
+    let users = GroupExpander::new(&conn, false).expand("all").unwrap().users;
+    let names: Vec<String> = users.iter().map(|u| u.account()).collect();
+    assert_eq!(names, ["alice", "bob", "dave", "carol"]);
+}
+
+#[test]
+fn group_cycle_is_detected() {
+    let _env = FakeGerrit::new().install();
+    save_groups(&[
+        ("a", &["alice"], &["b"]),
+        ("b", &["bob"], &["c"]),
+        ("c", &[], &["a"]),
+        ("d", &[], &["missing"]),
+    ]);
+
+    let conn = change(SSH_URL, "I123").conn;
+    let expander = GroupExpander::new(&conn, false);
+    assert_eq!(expander.expand("a").unwrap_err(), "Group cycle detected: a -> b -> c -> a");
+    assert_eq!(expander.expand("b").unwrap_err(), "Group cycle detected: b -> c -> a -> b");
+    assert_eq!(expander.expand("d").unwrap_err(), "Group missing not found");
+}
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+use crate::os;
+use crate::os::{CommandError, CommandHandler};
+use crate::plan;
+use crate::gerrit_if::GerritChange;
+use crate::gerrit_if;
+
+// The command handler and the dry-run flag are global, tests using them run one by one
+static SERIAL: Mutex<()> = Mutex::new(());
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub const SSH_URL: &str = "ssh://bob@gerrit.example.com:29418/proj";
+
+pub fn fixture(name: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name);
+    fs::read_to_string(&path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path.display(), e))
+}
+
+pub fn change(url: &str, change_id: &str) -> GerritChange {
+    GerritChange {
+        conn: gerrit_if::get_remote_conn(Some(url), "origin").unwrap(),
+        change_id: change_id.to_string(),
+    }
+}
+
+/// Gerrit behind `ssh` and `git` which answers with recorded fixtures.
+/// Other commands, e.g. curl talking to an HttpStub, run for real.
+pub struct FakeGerrit {
+    queries: Vec<(Vec<String>, String)>,
+    settings: HashMap<String, String>,
+    git_dir: PathBuf,
+    ssh_failures: AtomicUsize,
+    calls: Mutex<Vec<Vec<String>>>,
+}
+
+impl FakeGerrit {
+    pub fn new() -> Self {
+        let git_dir = std::env::temp_dir().join(format!("gerreviewer-test-{}-{}",
+            std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::SeqCst)));
+        fs::create_dir_all(git_dir.join(".git")).unwrap();
+
+        let mut settings = HashMap::new();
+        // Never look up real credentials of the test machine
+        settings.insert("gerreviewer.httpAuth".to_string(), "env".to_string());
+        Self {
+            queries: Vec::new(),
+            settings,
+            git_dir,
+            ssh_failures: AtomicUsize::new(0),
+            calls: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Answer `gerrit query` containing all the words with the fixture
+    pub fn on_query(mut self, words: &[&str], fixture_name: &str) -> Self {
+        let words = words.iter().map(|w| w.to_string()).collect();
+        self.queries.push((words, fixture(fixture_name)));
+        self
+    }
+
+    pub fn setting(mut self, name: &str, value: &str) -> Self {
+        self.settings.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// The next n ssh calls fail like an unreachable host
+    pub fn fail_ssh(self, n: usize) -> Self {
+        self.ssh_failures.store(n, Ordering::SeqCst);
+        self
+    }
+
+    pub fn install(self) -> Installed {
+        let lock = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let fake = Arc::new(self);
+        os::set_command_handler(Some(fake.clone()));
+        plan::set_dry_run(false);
+        os::reset_cancelled();
+        Installed { fake, _lock: lock }
+    }
+
+    fn handle_git(&self, args: &[String]) -> Result<String, CommandError> {
+        let not_found = || CommandError::Exit { code: Some(1), stderr: String::new() };
+        match args.first().map(|s| s.as_str()) {
+            Some("config") => {
+                let name = args.last().unwrap();
+                self.settings.get(name).cloned().ok_or_else(not_found)
+            },
+            Some("rev-parse") if args.iter().any(|a| a == "--git-dir") => {
+                Ok(format!("{}\n.git\n", self.git_dir.display()))
+            },
+            Some("rev-parse") if args.iter().any(|a| a == "--show-toplevel") => {
+                Ok(format!("{}\n", self.git_dir.display()))
+            },
+            Some("remote") => {
+                let remotes: Vec<&str> = self.settings.keys()
+                    .filter_map(|k| k.strip_prefix("remote.")?.strip_suffix(".url"))
+                    .collect();
+                Ok(remotes.join("\n"))
+            },
+            Some("show") => {
+                let (rev, path) = args[1].split_once(':').ok_or_else(not_found)?;
+                fs::read_to_string(self.git_dir.join(".git").join("trees").join(rev).join(path))
+                    .map_err(|_| not_found())
+            },
+            _ => Err(not_found()),
+        }
+    }
+
+    fn handle_ssh(&self, argv: &[String]) -> Result<String, CommandError> {
+        if self.ssh_failures.load(Ordering::SeqCst) > 0 {
+            self.ssh_failures.fetch_sub(1, Ordering::SeqCst);
+            return Err(CommandError::Exit { code: Some(255), stderr: "Connection refused".to_string() });
+        }
+        let pos = argv.iter().position(|a| a == "gerrit").expect("Not a gerrit command");
+        let remote = argv[pos + 1..].join(" ");
+        match argv.get(pos + 1).map(|s| s.as_str()) {
+            Some("query") => {
+                for (words, fixture) in &self.queries {
+                    if words.iter().all(|w| remote.contains(w.as_str())) {
+                        return Ok(fixture.clone());
+                    }
+                }
+                Ok("{\"type\":\"stats\",\"rowCount\":0}\n".to_string())
+            },
+            Some("set-reviewers") => Ok(String::new()),
+            _ => Err(CommandError::Exit { code: Some(1), stderr: format!("fatal: {}: not found", remote) }),
+        }
+    }
+}
+
+impl CommandHandler for FakeGerrit {
+    fn handle(&self, argv: &[String], _input: Option<&str>) -> Option<Result<String, CommandError>> {
+        match argv[0].as_str() {
+            "git" => Some(self.handle_git(&argv[1..])),
+            "ssh" => {
+                self.calls.lock().unwrap().push(argv.to_vec());
+                Some(self.handle_ssh(argv))
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Removes the fake when the test ends
+pub struct Installed {
+    pub fake: Arc<FakeGerrit>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Installed {
+    /// Remote arguments of the ssh calls running the gerrit command
+    pub fn gerrit_calls(&self, command: &str) -> Vec<Vec<String>> {
+        self.fake.calls.lock().unwrap().iter()
+            .filter_map(|argv| {
+                let pos = argv.iter().position(|a| a == "gerrit")?;
+                if argv.get(pos + 1).map(|s| s.as_str()) == Some(command) {
+                    Some(argv[pos + 2..].to_vec())
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn ssh_calls(&self) -> Vec<Vec<String>> {
+        self.fake.calls.lock().unwrap().clone()
+    }
+
+    pub fn data_file(&self, name: &str) -> PathBuf {
+        self.fake.git_dir.join(".git").join(name)
+    }
+
+    /// File in the working tree
+    pub fn work_file(&self, path: &str) -> PathBuf {
+        self.fake.git_dir.join(path)
+    }
+
+    /// File of the tree of a revision, e.g. "origin/main", read by `git show`
+    pub fn tree_file(&self, rev: &str, path: &str) -> PathBuf {
+        self.fake.git_dir.join(".git").join("trees").join(rev).join(path)
+    }
+}
+
+impl Drop for Installed {
+    fn drop(&mut self) {
+        os::set_command_handler(None);
+        plan::set_dry_run(false);
+        let _ = fs::remove_dir_all(&self.fake.git_dir);
+    }
+}
+
+pub struct StubRequest {
+    pub method: String,
+    pub path: String,
+    pub authorization: Option<String>,
+    pub body: String,
+}
+
+struct Route {
+    method: String,
+    words: Vec<String>,
+    status: u16,
+    body: String,
+}
+
+/// Local HTTP server answering the Gerrit REST endpoints with fixtures
+pub struct HttpStub {
+    routes: Vec<Route>,
+    requests: Arc<Mutex<Vec<StubRequest>>>,
+}
+
+impl HttpStub {
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            requests: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Answer requests whose path contains all the words
+    pub fn route(mut self, method: &str, words: &[&str], status: u16, body: &str) -> Self {
+        self.routes.push(Route {
+            method: method.to_string(),
+            words: words.iter().map(|w| w.to_string()).collect(),
+            status,
+            body: body.to_string(),
+        });
+        self
+    }
+
+    /// Serve in the background, return the url of the project and the recorded requests
+    pub fn start(self, project: &str) -> (String, Arc<Mutex<Vec<StubRequest>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://bob@127.0.0.1:{}/{}", listener.local_addr().unwrap().port(), project);
+        let requests = self.requests.clone();
+        let routes = Arc::new(self.routes);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                serve(stream, &routes, &self.requests);
+            }
+        });
+        (url, requests)
+    }
+}
+
+fn serve(mut stream: TcpStream, routes: &[Route], requests: &Mutex<Vec<StubRequest>>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut line = String::new();
+    if reader.read_line(&mut line).is_err() {
+        return;
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut length = 0;
+    let mut authorization = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).is_err() || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            match name.to_ascii_lowercase().as_str() {
+                "content-length" => length = value.trim().parse().unwrap_or(0),
+                "authorization" => authorization = Some(value.trim().to_string()),
+                _ => {},
+            }
+        }
+    }
+    let mut body = vec![0; length];
+    let _ = reader.read_exact(&mut body);
+
+    let (status, resp) = match routes.iter().find(|r| r.method == method && r.words.iter().all(|w| path.contains(w.as_str()))) {
+        Some(r) => (r.status, r.body.clone()),
+        None => (404, "Not found".to_string()),
+    };
+    requests.lock().unwrap().push(StubRequest {
+        method,
+        path,
+        authorization,
+        body: String::from_utf8_lossy(&body).to_string(),
+    });
+
+    let _ = write!(stream, "HTTP/1.1 {} Stub\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                   status, resp.len(), resp);
+}
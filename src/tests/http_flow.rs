@@ -0,0 +1,99 @@
+use crate::gerrit_if;
+use crate::candidates;
+use crate::users::{GerritUser, UserIdentifier};
+
+use super::harness::{FakeGerrit, HttpStub, change, fixture};
+
+#[test]
+fn add_reviewers_over_rest() {
+    let _env = FakeGerrit::new().install();
+    let (url, requests) = HttpStub::new()
+        .route("GET", &["/changes/?q=", "I123"], 200, &fixture("rest/change.json"))
+        .route("POST", &["/changes/I123/revisions/current/review"], 200, ")]}'\n{}")
+        .start("proj");
+
+    let users = vec![GerritUser::from_str("alice"), GerritUser::from_str("carol")];
+    gerrit_if::add_reviewers(&change(&url, "I123"), &users).unwrap();
+
+    let requests = requests.lock().unwrap();
+    let post = requests.iter().find(|r| r.method == "POST").expect("No POST request");
+    let body: serde_json::Value = serde_json::from_str(&post.body).unwrap();
+    assert_eq!(body, serde_json::json!({ "reviewers": [{ "reviewer": "alice", "state": "REVIEWER" }] }));
+    // No credential, so the anonymous endpoints are used
+    assert!(post.authorization.is_none());
+    assert!(requests.iter().all(|r| !r.path.starts_with("/a/")));
+}
+
+#[test]
+fn reviewers_and_uploader_from_rest() {
+    let _env = FakeGerrit::new().install();
+    let (url, _) = HttpStub::new()
+        .route("GET", &["/changes/?q=", "I123"], 200, &fixture("rest/change.json"))
+        .start("proj");
+
+    let c = change(&url, "I123");
+    let reviewers = gerrit_if::get_reviewers(&c).unwrap();
+    assert!(reviewers[0].same_account(&GerritUser::from_str("carol")));
+    assert_eq!(reviewers.len(), 1);
+    let owners = gerrit_if::get_change_owners(&c).unwrap();
+    let owners: Vec<String> = owners.iter().map(|u| u.account()).collect();
+    assert_eq!(owners, vec!["bob", "eve"]);
+}
+
+#[test]
+fn suggest_over_rest_checks_account_status() {
+    let _env = FakeGerrit::new().install();
+    let (url, _) = HttpStub::new()
+        .route("GET", &["/changes/?q=", "owner%3Abob"], 200, &fixture("rest/recent.json"))
+        .route("GET", &["/changes/?q=", "I123"], 200, &fixture("rest/change.json"))
+        .route("GET", &["/accounts/alice"], 200, &fixture("rest/account_alice.json"))
+        .route("GET", &["/accounts/dave"], 200, &fixture("rest/account_dave.json"))
+        .start("proj");
+
+    let rows = candidates::suggest(&change(&url, "I123")).unwrap();
+    let found: Vec<(&str, &str)> = rows.iter()
+        .map(|r| (r.user.username.as_str(), r.state.as_str()))
+        .collect();
+    assert_eq!(found, vec![("dave", "CANDIDATE"), ("alice", "UNAVAILABLE")]);
+}
+
+#[test]
+fn auth_error_is_clear() {
+    let _env = FakeGerrit::new().install();
+    let (url, _) = HttpStub::new()
+        .route("GET", &["/changes/"], 401, "Unauthorized")
+        .start("proj");
+
+    let err = gerrit_if::get_reviewers(&change(&url, "I123")).unwrap_err();
+    assert!(err.starts_with("Authentication required by 127.0.0.1"), "{}", err);
+}
+
+#[test]
+fn accounts_without_username_stay_distinct() {
+    let _env = FakeGerrit::new().install();
+    let (url, requests) = HttpStub::new()
+        .route("GET", &["/changes/?q=", "owner%3Abob"], 200, &fixture("rest/recent_no_username.json"))
+        .route("GET", &["/changes/?q=", "I123"], 200, &fixture("rest/change.json"))
+        .route("POST", &["/changes/I123/revisions/current/review"], 200, ")]}'\n{}")
+        .start("proj");
+
+    let c = change(&url, "I123");
+    let counts = gerrit_if::get_user_recent_reviewer_counts(&c.conn, &GerritUser::from_str("bob")).unwrap();
+    let found: Vec<(String, usize)> = counts.iter().map(|(u, n)| (u.account(), *n)).collect();
+    assert_eq!(found, vec![("1005".to_string(), 1), ("1006".to_string(), 2), ("alice".to_string(), 1)]);
+
+    // Users given by email or account id match the queried accounts
+    assert!(counts[1].0.same_account(&UserIdentifier::parse("frank@example.com").unwrap().to_user()));
+    let bot = GerritUser { account_id: Some(1005), ..GerritUser::from_str("") };
+    assert_eq!(counts[0].0, bot);
+    assert_ne!(counts[1].0, bot);
+    // Equal users hash alike
+    let set: crate::users::UsersTy = counts.iter().map(|(u, _)| u.clone()).chain([bot.clone()]).collect();
+    assert_eq!(set.len(), 3);
+
+    gerrit_if::add_reviewers(&c, &[bot]).unwrap();
+    let requests = requests.lock().unwrap();
+    let post = requests.iter().find(|r| r.method == "POST").expect("No POST request");
+    let body: serde_json::Value = serde_json::from_str(&post.body).unwrap();
+    assert_eq!(body, serde_json::json!({ "reviewers": [{ "reviewer": "1005", "state": "REVIEWER" }] }));
+}
@@ -0,0 +1,48 @@
+use crate::inbox;
+use crate::users::GerritUser;
+
+use super::harness::{FakeGerrit, HttpStub, SSH_URL, change, fixture};
+
+// number, project, branch, owner, votes, attention
+type Row<'a> = (&'a str, &'a str, &'a str, String, Vec<String>, bool);
+
+#[test]
+fn inbox_over_ssh() {
+    let env = FakeGerrit::new().on_query(&["attention:bob"], "ssh/inbox.json").install();
+
+    let conn = change(SSH_URL, "I123").conn;
+    let entries = inbox::get_inbox(&conn, &GerritUser::from_str("bob")).unwrap();
+    let found: Vec<Row> = entries.iter()
+        .map(|e| (e.number.as_str(), e.change.conn.project(), e.branch.as_str(), e.owner.account(), e.my_votes.clone(), e.attention))
+        .collect();
+    assert_eq!(found, vec![
+        ("61", "proj-a", "main", "carol".to_string(), vec!["Code-Review+1".to_string()], false),
+        ("62", "proj-b", "dev", "dave".to_string(), vec![], true),
+    ]);
+    assert!(entries[0].age > entries[1].age);
+    // Changes are managed in their own project
+    assert_eq!(entries[1].change.change_id, "62");
+
+    let calls = env.gerrit_calls("query");
+    let query = calls[0].last().unwrap();
+    assert!(query.contains("status:open (reviewer:bob OR attention:bob) -owner:bob"), "{}", query);
+    assert!(!query.contains("project:"), "{}", query);
+}
+
+#[test]
+fn inbox_over_rest() {
+    let _env = FakeGerrit::new().install();
+    let (url, requests) = HttpStub::new()
+        .route("GET", &["/changes/?q=", "attention%3Abob"], 200, &fixture("rest/inbox.json"))
+        .start("proj");
+
+    let conn = change(&url, "I123").conn;
+    let entries = inbox::get_inbox(&conn, &GerritUser::from_str("bob")).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].my_votes, vec!["Code-Review-1".to_string()]);
+    assert!(entries[0].attention);
+    assert_eq!(entries[0].change.conn.project(), "proj-a");
+
+    let requests = requests.lock().unwrap();
+    assert!(requests[0].path.contains("o=DETAILED_LABELS"), "{}", requests[0].path);
+}
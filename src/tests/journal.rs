@@ -0,0 +1,100 @@
+use crate::gerrit_if;
+use crate::journal;
+use crate::plan;
+use crate::users::GerritUser;
+
+use super::harness::{FakeGerrit, HttpStub, SSH_URL, change, fixture};
+
+#[test]
+fn undo_account_without_username() {
+    let _env = FakeGerrit::new().install();
+    let (url, requests) = HttpStub::new()
+        .route("GET", &["/changes/?q=", "I123"], 200, &fixture("rest/change_id_only.json"))
+        .route("POST", &["/changes/I123/revisions/current/review"], 200, ")]}'\n{}")
+        .start("proj");
+
+    let c = change(&url, "I123");
+    let after = gerrit_if::get_reviewers(&c).unwrap();
+    journal::record(&c, &after[..1], &after, &[]).unwrap();
+    assert_eq!(journal::load()[0].after, vec!["carol", "1007"]);
+
+    journal::undo().unwrap();
+    let requests = requests.lock().unwrap();
+    let post = requests.iter().find(|r| r.method == "POST").expect("No POST request");
+    let body: serde_json::Value = serde_json::from_str(&post.body).unwrap();
+    assert_eq!(body, serde_json::json!({ "reviewers": [{ "reviewer": "1007", "state": "REMOVED" }] }));
+    assert!(journal::load()[0].undone);
+}
+
+#[test]
+fn next_undo_skips_undone_entries() {
+    let _env = FakeGerrit::new().install();
+    let (url, _) = HttpStub::new()
+        .route("GET", &["/changes/?q=", "I123"], 200, &fixture("rest/change_id_only.json"))
+        .route("POST", &["/changes/I123/revisions/current/review"], 200, ")]}'\n{}")
+        .start("proj");
+    assert!(journal::next_undo().is_none());
+
+    let c = change(&url, "I123");
+    let reviewers = gerrit_if::get_reviewers(&c).unwrap();
+    journal::record(&c, &[], &reviewers[..1], &[]).unwrap();
+    journal::record(&c, &reviewers[..1], &reviewers, &[]).unwrap();
+    assert_eq!(journal::next_undo().unwrap().after, vec!["carol", "1007"]);
+
+    journal::undo().unwrap();
+    // The newest entry is undone, the next undo reverts the one before
+    let next = journal::next_undo().unwrap();
+    assert_eq!((next.before.len(), next.after.clone()), (0, vec!["carol".to_string()]));
+    journal::undo().unwrap();
+    assert!(journal::next_undo().is_none());
+    assert_eq!(journal::undo().unwrap_err(), "Nothing to undo");
+}
+
+#[test]
+fn undo_restores_removed_reviewers() {
+    let env = FakeGerrit::new()
+        .on_query(&["--all-reviewers", "I123"], "ssh/reviewers.json")
+        .install();
+
+    let c = change(SSH_URL, "I123");
+    let after = gerrit_if::get_reviewers(&c).unwrap();
+    let mut before = after.clone();
+    before.push(GerritUser::from_str("erin"));
+    journal::record(&c, &before, &after, &[]).unwrap();
+
+    plan::set_dry_run(true);
+    let msg = journal::undo().unwrap();
+    assert!(msg.starts_with("Undo: "), "{}", msg);
+    assert!(msg.contains("Would run: "), "{}", msg);
+    assert!(env.gerrit_calls("set-reviewers").is_empty());
+    assert!(!journal::load()[0].undone);
+
+    plan::set_dry_run(false);
+    journal::undo().unwrap();
+    assert_eq!(env.gerrit_calls("set-reviewers"), vec![
+        ["-p", "proj", "--add", "erin", "I123"].iter().map(|s| s.to_string()).collect::<Vec<String>>(),
+    ]);
+    assert!(journal::load()[0].undone);
+}
+
+#[test]
+fn undo_removes_added_ccs() {
+    let _env = FakeGerrit::new().install();
+    let (url, requests) = HttpStub::new()
+        .route("GET", &["/changes/?q=", "I123"], 200, &fixture("rest/change_id_only.json"))
+        .route("POST", &["/changes/I123/revisions/current/review"], 200, ")]}'\n{}")
+        .start("proj");
+
+    let c = change(&url, "I123");
+    let mut plan = plan::ReviewerPlan::new(&c);
+    plan.ccs = vec![GerritUser::from_str("dave"), GerritUser::from_str("carol")];
+    gerrit_if::apply_plan(&plan).unwrap();
+    // carol is a reviewer, undo keeps her
+    assert_eq!(journal::load()[0].ccs, vec!["dave"]);
+
+    journal::undo().unwrap();
+    let requests = requests.lock().unwrap();
+    let post = requests.iter().rev().find(|r| r.method == "POST").expect("No POST request");
+    let body: serde_json::Value = serde_json::from_str(&post.body).unwrap();
+    assert_eq!(body, serde_json::json!({ "reviewers": [{ "reviewer": "dave", "state": "REMOVED" }] }));
+}
@@ -0,0 +1,15 @@
+mod harness;
+mod ssh_flow;
+mod http_flow;
+mod pool;
+mod groups_config;
+mod stats;
+mod inbox;
+mod votes;
+mod requirements;
+mod journal;
+mod selection;
+mod trailers;
+mod candidates;
+mod availability;
+mod auth;
@@ -0,0 +1,36 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::pool;
+
+#[test]
+fn map_keeps_order() {
+    let items: Vec<usize> = (0..20).collect();
+    let ret = pool::map(items, 4, |i| {
+        // Later items finish first
+        thread::sleep(Duration::from_millis(20 - i as u64));
+        i * 2
+    });
+    assert_eq!(ret, (0..20).map(|i| i * 2).collect::<Vec<usize>>());
+}
+
+#[test]
+fn map_is_bounded() {
+    let running = AtomicUsize::new(0);
+    let peak = AtomicUsize::new(0);
+    pool::map((0..12).collect::<Vec<usize>>(), 3, |_| {
+        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+        peak.fetch_max(now, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(20));
+        running.fetch_sub(1, Ordering::SeqCst);
+    });
+    let peak = peak.load(Ordering::SeqCst);
+    assert!(peak > 1 && peak <= 3, "peak {}", peak);
+}
+
+#[test]
+fn join_runs_both() {
+    let (a, b) = pool::join(|| 1, || "two");
+    assert_eq!((a, b), (1, "two"));
+}
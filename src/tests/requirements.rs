@@ -0,0 +1,136 @@
+use std::fs;
+use std::path::Path;
+
+use crate::config::{ConfigScope, GroupsConfig};
+use crate::groups::GerritUserGroup;
+use crate::candidates::UserRow;
+use crate::requirements;
+use crate::requirements::Gap;
+use crate::users::GerritUser;
+
+use super::harness::{FakeGerrit, HttpStub, SSH_URL, change, fixture};
+
+fn write(path: &Path, contents: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+
+fn names<'a>(rows: impl IntoIterator<Item = &'a UserRow>) -> Vec<(String, Vec<String>)> {
+    rows.into_iter().map(|r| (r.user.account(), r.reasons.clone())).collect()
+}
+
+fn candidates(gap: &Gap) -> Vec<(String, Vec<String>)> {
+    names(gap.candidates.iter().map(|c| &c.row))
+}
+
+fn strings(s: &[&str]) -> Vec<String> {
+    s.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn owners_files() {
+    let env = FakeGerrit::new().install();
+    write(&env.work_file("OWNERS"), "erin@example.com\n");
+    write(&env.work_file("src/OWNERS"), "alice\n# Docs\nper-file *.md=frank\n");
+    write(&env.work_file("src/lexer/OWNERS"), "set noparent\ngrace@example.com  # lead\nper-file *.rs,*.toml = alice@example.com\ninclude /infra/OWNERS\n");
+
+    let top = env.work_file("");
+    let owners = |file: &str| -> Vec<(String, String)> {
+        let read = |path: &Path| fs::read_to_string(top.join(path)).ok();
+        requirements::owners_of_file(read, file).into_iter().map(|(u, d)| (u.account(), d)).collect()
+    };
+    assert_eq!(owners("src/lexer/mod.rs"), vec![
+        ("grace@example.com".to_string(), "src/lexer".to_string()),
+        ("alice@example.com".to_string(), "src/lexer".to_string()),
+    ]);
+    assert_eq!(owners("src/README.md"), vec![
+        ("alice".to_string(), "src".to_string()),
+        ("frank".to_string(), "src".to_string()),
+        ("erin@example.com".to_string(), "/".to_string()),
+    ]);
+    assert_eq!(owners("docs/guide.txt"), vec![("erin@example.com".to_string(), "/".to_string())]);
+}
+
+#[test]
+fn gaps_over_ssh() {
+    let env = FakeGerrit::new()
+        .on_query(&["status:merged", "branch:main"], "ssh/history.json")
+        .on_query(&["I123"], "ssh/requirements.json")
+        .setting("remote.origin.url", SSH_URL)
+        .install();
+    write(&env.tree_file("origin/main", "OWNERS"), "erin@example.com\n");
+    write(&env.tree_file("origin/main", "src/lexer/OWNERS"), "set noparent\ngrace@example.com\nper-file *.rs=alice@example.com\n");
+    // The working tree is another branch
+    write(&env.work_file("OWNERS"), "ivan@example.com\n");
+    let mut cfg = GroupsConfig::new(ConfigScope::Local);
+    let mut core = GerritUserGroup::new("core");
+    core.users.insert(GerritUser::from_str("alice"));
+    core.users.insert(GerritUser::from_str("henry"));
+    cfg.insert_group(core).unwrap();
+    cfg.save().unwrap();
+
+    let gaps = requirements::analyze(&change(SSH_URL, "I123")).unwrap();
+    assert_eq!(gaps.len(), 2);
+    assert!(gaps[0].requirement.satisfied);
+    assert!(gaps[0].candidates.is_empty());
+
+    let cr = &gaps[1];
+    assert_eq!((cr.requirement.name.as_str(), cr.requirement.satisfied, cr.needed), ("Code-Review", false, Some(2)));
+    assert_eq!(names(&cr.reviewers), vec![
+        ("dave".to_string(), strings(&["gave Code-Review+2 on 1 of 2 merged changes of main"])),
+    ]);
+    assert_eq!(candidates(cr), vec![
+        ("alice".to_string(), strings(&["group core", "OWNERS of src/lexer", "gave Code-Review+2 on 2 of 2 merged changes of main"])),
+        ("erin".to_string(), strings(&["OWNERS of /", "gave Code-Review+2 on 1 of 2 merged changes of main"])),
+    ]);
+
+    let queries = env.gerrit_calls("query");
+    assert!(queries[0].iter().any(|a| a == "--submit-records"));
+    assert!(queries[0].iter().any(|a| a == "--files"));
+}
+
+#[test]
+fn gaps_over_rest() {
+    let (url, requests) = HttpStub::new()
+        .route("GET", &["/changes/?q=", "status%3Amerged"], 200, &fixture("rest/history.json"))
+        .route("GET", &["/changes/?q=", "I123"], 200, &fixture("rest/requirements.json"))
+        .start("proj");
+    let env = FakeGerrit::new().setting("remote.origin.url", &url).install();
+    write(&env.tree_file("origin/main", "OWNERS"), "alice\n");
+
+    let gaps = requirements::analyze(&change(&url, "I123")).unwrap();
+    let found: Vec<(&str, bool, Option<&str>, Option<i64>)> = gaps.iter()
+        .map(|g| (g.requirement.name.as_str(), g.requirement.satisfied, g.requirement.label.as_deref(), g.needed))
+        .collect();
+    assert_eq!(found, vec![
+        ("Code-Review", false, Some("Code-Review"), Some(2)),
+        ("Verified", true, Some("Verified"), None),
+        ("No-Unresolved-Comments", false, None, None),
+    ]);
+    // Carol may only vote +1
+    assert_eq!(names(&gaps[0].reviewers), vec![("dave".to_string(), strings(&["may vote +2"]))]);
+    // Owners first, even behind voters with more reasons
+    assert_eq!(candidates(&gaps[0]), vec![
+        ("alice".to_string(), strings(&["OWNERS of /", "gave Code-Review+2 on 1 of 2 merged changes of main"])),
+        ("frank".to_string(), strings(&["may vote +2", "gave Code-Review+2 on 2 of 2 merged changes of main"])),
+    ]);
+
+    let requests = requests.lock().unwrap();
+    assert!(requests[0].path.contains("o=SUBMIT_REQUIREMENTS"), "{}", requests[0].path);
+}
+
+#[test]
+fn owners_skipped_for_other_projects() {
+    let env = FakeGerrit::new()
+        .on_query(&["status:merged", "branch:main"], "ssh/history.json")
+        .on_query(&["I123"], "ssh/requirements.json")
+        .setting("remote.origin.url", "ssh://bob@gerrit.example.com:29418/other")
+        .install();
+    write(&env.tree_file("origin/main", "OWNERS"), "erin@example.com\n");
+
+    let gaps = requirements::analyze(&change(SSH_URL, "I123")).unwrap();
+    assert_eq!(candidates(&gaps[1]), vec![
+        ("alice".to_string(), strings(&["gave Code-Review+2 on 2 of 2 merged changes of main"])),
+        ("erin".to_string(), strings(&["gave Code-Review+2 on 1 of 2 merged changes of main"])),
+    ]);
+}
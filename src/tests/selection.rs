@@ -0,0 +1,172 @@
+use crate::config::{ConfigScope, GroupsConfig};
+use crate::groups::{GerritUserGroup, GroupRef};
+use crate::gerrit_if;
+use crate::selection;
+use crate::selection::{AssignmentHistory, SelectMode};
+use crate::candidates::CandidateFilter;
+use crate::users::GerritUser;
+
+use super::harness::{FakeGerrit, HttpStub, SSH_URL, change};
+
+const CORE_MEMBERS: &str = r#")]}'
+[{"_account_id":1002,"name":"Carol","email":"carol@example.com","username":"carol"},
+ {"_account_id":1003,"name":"Dave","email":"dave@example.com","username":"dave"}]"#;
+
+fn changes(n: usize) -> String {
+    page(n, false)
+}
+
+// The last change of a page tells if there are more
+fn page(n: usize, more: bool) -> String {
+    let mut changes: Vec<String> = (0..n).map(|i| format!("{{\"_number\":{}}}", i + 1)).collect();
+    if more {
+        changes[n - 1] = format!("{{\"_number\":{},\"_more_changes\":true}}", n);
+    }
+    format!(")]}}'\n[{}]", changes.join(","))
+}
+
+fn save_team(mode: SelectMode) {
+    let mut team = GerritUserGroup::new("team");
+    team.users.insert(GerritUser::from_str("alice"));
+    team.includes.insert(GroupRef::Gerrit("Core".to_string()));
+    team.mode = mode;
+    let mut cfg = GroupsConfig::new(ConfigScope::Local);
+    cfg.insert_group(team).unwrap();
+    cfg.save().unwrap();
+}
+
+fn accounts(users: &[GerritUser]) -> Vec<String> {
+    users.iter().map(|u| u.account()).collect()
+}
+
+#[test]
+fn least_loaded_expands_gerrit_groups() {
+    let _env = FakeGerrit::new().install();
+    let (url, _) = HttpStub::new()
+        .route("GET", &["/groups/Core/members/"], 200, CORE_MEMBERS)
+        .route("GET", &["/changes/?q=", "reviewer%3Aalice"], 200, &changes(2))
+        .route("GET", &["/changes/?q=", "reviewer%3Acarol"], 200, &changes(0))
+        .route("GET", &["/changes/?q=", "reviewer%3Adave"], 200, &changes(1))
+        .start("proj");
+    save_team(SelectMode::LeastLoaded);
+
+    let conn = change(&url, "I123").conn;
+    let filter = CandidateFilter::for_owner(Some(GerritUser::from_str("bob")));
+    let picked = selection::pick_from_group("team", &conn, &filter, &AssignmentHistory::new()).unwrap();
+    assert_eq!(accounts(&picked.users), vec!["carol"]);
+}
+
+#[test]
+fn least_loaded_fails_without_load() {
+    let _env = FakeGerrit::new().install();
+    let (url, _) = HttpStub::new()
+        .route("GET", &["/groups/Core/members/"], 200, CORE_MEMBERS)
+        .route("GET", &["/changes/?q=", "reviewer%3Aalice"], 200, &changes(2))
+        .route("GET", &["/changes/?q=", "reviewer%3Acarol"], 200, &changes(0))
+        .start("proj");
+    save_team(SelectMode::LeastLoaded);
+
+    let conn = change(&url, "I123").conn;
+    let filter = CandidateFilter::for_owner(None);
+    let err = selection::pick_from_group("team", &conn, &filter, &AssignmentHistory::new()).unwrap_err();
+    assert!(err.starts_with("Failed to query open reviews of dave"), "{}", err);
+}
+
+#[test]
+fn all_keeps_gerrit_groups() {
+    let _env = FakeGerrit::new().install();
+    let (url, requests) = HttpStub::new().start("proj");
+    save_team(SelectMode::All);
+
+    let conn = change(&url, "I123").conn;
+    let filter = CandidateFilter::for_owner(None);
+    let picked = selection::pick_from_group("team", &conn, &filter, &AssignmentHistory::new()).unwrap();
+    // Gerrit adds the members of the group itself
+    assert_eq!(accounts(&picked.users), vec!["alice"]);
+    assert_eq!(picked.groups, vec!["Core"]);
+    assert!(requests.lock().unwrap().iter().all(|r| !r.path.starts_with("/groups/")));
+}
+
+#[test]
+fn open_reviews_are_counted_over_pages() {
+    let _env = FakeGerrit::new().install();
+    let (url, requests) = HttpStub::new()
+        .route("GET", &["/changes/?q=", "reviewer%3Aalice", "S=100"], 200, &changes(30))
+        .route("GET", &["/changes/?q=", "reviewer%3Aalice"], 200, &page(100, true))
+        .start("proj");
+
+    let conn = change(&url, "I123").conn;
+    assert_eq!(gerrit_if::count_open_reviews(&conn, &GerritUser::from_str("alice")).unwrap(), 130);
+    // Reviews of all projects count
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    assert!(requests.iter().all(|r| !r.path.contains("project%3A")), "{}", requests[0].path);
+}
+
+#[test]
+fn open_reviews_are_counted_up_to_cap() {
+    let _env = FakeGerrit::new().install();
+    let (url, requests) = HttpStub::new()
+        .route("GET", &["/changes/?q=", "reviewer%3Aalice"], 200, &page(100, true))
+        .start("proj");
+
+    let conn = change(&url, "I123").conn;
+    assert_eq!(gerrit_if::count_open_reviews(&conn, &GerritUser::from_str("alice")).unwrap(), 200);
+    assert_eq!(requests.lock().unwrap().len(), 2);
+}
+
+fn group(mode: SelectMode, pick: usize) -> GerritUserGroup {
+    let mut group = GerritUserGroup::new("team");
+    group.mode = mode;
+    group.pick = pick;
+    group
+}
+
+fn members() -> Vec<GerritUser> {
+    ["alice", "carol", "dave"].iter().map(|u| GerritUser::from_str(u)).collect()
+}
+
+#[test]
+fn all_ignores_pick() {
+    let _env = FakeGerrit::new().install();
+    let conn = change(SSH_URL, "I123").conn;
+    let picked = selection::select(&group(SelectMode::All, 1), members(), &conn, &AssignmentHistory::new()).unwrap();
+    assert_eq!(accounts(&picked), vec!["alice", "carol", "dave"]);
+}
+
+#[test]
+fn random_picks_distinct_members() {
+    let _env = FakeGerrit::new().install();
+    let conn = change(SSH_URL, "I123").conn;
+    let history = AssignmentHistory::new();
+    for _ in 0..10 {
+        let mut picked = accounts(&selection::select(&group(SelectMode::Random, 2), members(), &conn, &history).unwrap());
+        picked.sort();
+        picked.dedup();
+        assert_eq!(picked.len(), 2);
+        assert!(picked.iter().all(|u| ["alice", "carol", "dave"].contains(&u.as_str())), "{:?}", picked);
+    }
+    // Never more than the members
+    let picked = selection::select(&group(SelectMode::Random, 5), members(), &conn, &history).unwrap();
+    assert_eq!(picked.len(), 3);
+}
+
+#[test]
+fn round_robin_picks_least_recently_assigned() {
+    let _env = FakeGerrit::new().install();
+    let conn = change(SSH_URL, "I123").conn;
+    let team = group(SelectMode::RoundRobin, 2);
+
+    // Never assigned members keep the group order
+    let mut history = AssignmentHistory::new();
+    let picked = selection::select(&team, members(), &conn, &history).unwrap();
+    assert_eq!(accounts(&picked), vec!["alice", "carol"]);
+
+    history.record("team", &picked);
+    history.save().unwrap();
+    let history = AssignmentHistory::new();
+    assert_eq!(history.assigned_count("team", &GerritUser::from_str("alice")), 1);
+    assert_eq!(history.assigned_count("team", &GerritUser::from_str("dave")), 0);
+    let picked = selection::select(&team, members(), &conn, &history).unwrap();
+    assert_eq!(accounts(&picked)[0], "dave");
+}
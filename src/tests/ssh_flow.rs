@@ -0,0 +1,162 @@
+use crate::gerrit_if;
+use crate::candidates;
+use crate::plan;
+use crate::users::GerritUser;
+
+use super::harness::{FakeGerrit, SSH_URL, change};
+
+fn users(names: &[&str]) -> Vec<GerritUser> {
+    names.iter().map(|n| GerritUser::from_str(n)).collect()
+}
+
+fn accounts(users: &[GerritUser]) -> Vec<String> {
+    users.iter().map(|u| u.account()).collect()
+}
+
+fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|a| a.to_string()).collect()
+}
+
+#[test]
+fn get_reviewers_from_query() {
+    let env = FakeGerrit::new()
+        .on_query(&["--all-reviewers", "I123"], "ssh/reviewers.json")
+        .install();
+
+    let reviewers = gerrit_if::get_reviewers(&change(SSH_URL, "I123")).unwrap();
+    assert_eq!(accounts(&reviewers), strings(&["carol"]));
+    assert_eq!(reviewers[0].email, "carol@example.com");
+
+    let argv = &env.ssh_calls()[0];
+    assert_eq!(argv[..6], strings(&["ssh", "-x", "-p", "29418", "--", "bob@gerrit.example.com"]));
+    assert_eq!(env.gerrit_calls("query")[0],
+               strings(&["--format=JSON", "--all-reviewers", "'project:proj I123 limit:10'"]));
+}
+
+#[test]
+fn add_reviewers_over_ssh() {
+    let env = FakeGerrit::new()
+        .on_query(&["--all-reviewers", "I123"], "ssh/reviewers.json")
+        .install();
+
+    gerrit_if::add_reviewers(&change(SSH_URL, "I123"), &users(&["alice", "carol"])).unwrap();
+
+    // carol is a reviewer already
+    assert_eq!(env.gerrit_calls("set-reviewers"),
+               vec![strings(&["-p", "proj", "--add", "alice", "I123"])]);
+    let journal = std::fs::read_to_string(env.data_file("gerreviewer-journal.jsonl")).unwrap();
+    assert!(journal.contains("\"after\":[\"carol\",\"alice\"]"), "{}", journal);
+}
+
+#[test]
+fn gerrit_groups_are_added_by_name() {
+    let env = FakeGerrit::new()
+        .on_query(&["--all-reviewers", "I123"], "ssh/reviewers.json")
+        .install();
+
+    let c = change(SSH_URL, "I123");
+    let mut plan = plan::ReviewerPlan::new(&c);
+    plan.adds = users(&["alice"]);
+    plan.groups = strings(&["Core Team"]);
+    gerrit_if::apply_plan(&plan).unwrap();
+
+    // Gerrit expands the group, quoted as one argument of the remote command
+    assert_eq!(env.gerrit_calls("set-reviewers"),
+               vec![strings(&["-p", "proj", "--add", "alice", "--add", "'Core Team'", "I123"])]);
+}
+
+#[test]
+fn remove_reviewers_over_ssh() {
+    let env = FakeGerrit::new()
+        .on_query(&["--all-reviewers", "I123"], "ssh/reviewers.json")
+        .install();
+
+    gerrit_if::delete_reviewers(&change(SSH_URL, "I123"), &users(&["carol", "dave"])).unwrap();
+
+    assert_eq!(env.gerrit_calls("set-reviewers"),
+               vec![strings(&["-p", "proj", "--remove", "carol", "I123"])]);
+}
+
+#[test]
+fn nothing_sent_when_plan_is_empty() {
+    let env = FakeGerrit::new()
+        .on_query(&["--all-reviewers", "I123"], "ssh/reviewers.json")
+        .install();
+
+    let ret = gerrit_if::add_reviewers(&change(SSH_URL, "I123"), &users(&["carol"])).unwrap();
+    assert_eq!(ret, "carol is already a reviewer\nNothing happened");
+    assert!(env.gerrit_calls("set-reviewers").is_empty());
+}
+
+#[test]
+fn dry_run_sends_nothing() {
+    let env = FakeGerrit::new()
+        .on_query(&["--all-reviewers", "I123"], "ssh/reviewers.json")
+        .install();
+    plan::set_dry_run(true);
+
+    let ret = gerrit_if::add_reviewers(&change(SSH_URL, "I123"), &users(&["alice"])).unwrap();
+    assert!(ret.ends_with("set-reviewers -p proj --add alice I123"), "{}", ret);
+    assert!(env.gerrit_calls("set-reviewers").is_empty());
+    assert!(!env.data_file("gerreviewer-journal.jsonl").exists());
+}
+
+#[test]
+fn suggest_skips_owner_uploader_reviewers_and_bots() {
+    let _env = FakeGerrit::new()
+        .on_query(&["--all-reviewers", "owner:bob"], "ssh/recent.json")
+        .on_query(&["--all-reviewers", "I123"], "ssh/reviewers.json")
+        .on_query(&["--current-patch-set", "I123"], "ssh/owners.json")
+        .setting("gerreviewer.away", "dave 2000-01-01..")
+        .install();
+
+    let rows = candidates::suggest(&change(SSH_URL, "I123")).unwrap();
+    let found: Vec<(&str, &str, Option<i64>)> = rows.iter()
+        .map(|r| (r.user.username.as_str(), r.state.as_str(), r.score))
+        .collect();
+    assert_eq!(found, vec![("alice", "CANDIDATE", Some(2)), ("dave", "UNAVAILABLE", Some(1))]);
+}
+
+#[test]
+fn topic_is_quoted_in_query() {
+    let env = FakeGerrit::new().install();
+
+    let changes = gerrit_if::get_topic_changes(&change(SSH_URL, "I123").conn, "my topic").unwrap();
+    assert!(changes.is_empty());
+    assert_eq!(env.gerrit_calls("query")[0].last().unwrap(), "'status:open topic:\"my topic\" limit:100'");
+}
+
+#[test]
+fn topic_changes_follow_pages() {
+    let env = FakeGerrit::new()
+        .on_query(&["topic:big", "--start 2"], "ssh/topic_page2.json")
+        .on_query(&["topic:big"], "ssh/topic_page1.json")
+        .install();
+
+    let changes = gerrit_if::get_topic_changes(&change(SSH_URL, "I701").conn, "big").unwrap();
+    let found: Vec<(&str, &str)> = changes.iter()
+        .map(|c| (c.change_id.as_str(), c.conn.project()))
+        .collect();
+    assert_eq!(found, vec![("81", "proj"), ("82", "lib/common"), ("83", "docs")]);
+    assert_eq!(env.gerrit_calls("query").len(), 2);
+}
+
+#[test]
+fn unreachable_host_is_retried() {
+    let env = FakeGerrit::new()
+        .on_query(&["--all-reviewers", "I123"], "ssh/reviewers.json")
+        .fail_ssh(1)
+        .install();
+
+    let reviewers = gerrit_if::get_reviewers(&change(SSH_URL, "I123")).unwrap();
+    assert_eq!(accounts(&reviewers), strings(&["carol"]));
+    assert_eq!(env.ssh_calls().len(), 2);
+}
+
+#[test]
+fn error_tells_exit_code() {
+    let _env = FakeGerrit::new().fail_ssh(10).install();
+
+    let err = gerrit_if::get_reviewers(&change(SSH_URL, "I123")).unwrap_err();
+    assert_eq!(err, "ssh exited with code 255: Connection refused");
+}
@@ -0,0 +1,93 @@
+use crate::gerrit_if;
+use crate::stats;
+use crate::stats::StatsRequest;
+
+use super::harness::{FakeGerrit, HttpStub, SSH_URL, change, fixture};
+
+const OPTIONS: &[&str] = &["--all-reviewers", "--all-approvals", "--comments"];
+
+fn fake() -> FakeGerrit {
+    FakeGerrit::new()
+        .on_query(&["--start 2"], "ssh/stats_page2.json")
+        .on_query(&["after:2024-06-01"], "ssh/stats_page1.json")
+}
+
+#[test]
+fn query_follows_pages() {
+    let env = fake().install();
+
+    let conn = change(SSH_URL, "I401").conn;
+    let reviews = gerrit_if::query_all_reviews(&conn, OPTIONS, "after:2024-06-01", 100).unwrap();
+    let ids: Vec<&str> = reviews.iter().map(|r| r["id"].as_str().unwrap()).collect();
+    assert_eq!(ids, vec!["I401", "I402", "I403"]);
+    assert_eq!(env.gerrit_calls("query").len(), 2);
+
+    // Stops at the maximum, the second page is not needed
+    let before = env.gerrit_calls("query").len();
+    let reviews = gerrit_if::query_all_reviews(&conn, OPTIONS, "after:2024-06-01", 2).unwrap();
+    assert_eq!(reviews.len(), 2);
+    assert_eq!(env.gerrit_calls("query").len(), before + 1);
+}
+
+#[test]
+fn stats_per_reviewer() {
+    let _env = fake().install();
+
+    let conn = change(SSH_URL, "I401").conn;
+    let reviews = gerrit_if::query_all_reviews(&conn, OPTIONS, "after:2024-06-01", 100).unwrap();
+    let found: Vec<(String, usize, usize, Option<i64>, usize)> = stats::collect(&reviews).iter()
+        .map(|s| (s.user.username.clone(), s.reviews, s.votes, s.median_first_review(), s.open_queue))
+        .collect();
+    assert_eq!(found, vec![
+        ("alice".to_string(), 2, 1, Some(5400), 1),
+        ("carol".to_string(), 2, 1, Some(4500), 0),
+        ("dave".to_string(), 2, 0, Some(90000), 2),
+    ]);
+    assert_eq!(stats::format_duration(90000), "1d 1h");
+    assert_eq!(stats::format_duration(5400), "1h 30m");
+}
+
+#[test]
+fn stats_command_builds_query() {
+    let env = fake().install();
+
+    let req = StatsRequest {
+        since: Some("2024-06-01".to_string()),
+        until: Some("2024-06-30".to_string()),
+        project: None,
+        branch: Some("main".to_string()),
+        remote: "origin".to_string(),
+        max: 1000,
+    };
+    stats::run(&req, Some(SSH_URL)).unwrap();
+
+    let calls = env.gerrit_calls("query");
+    let query = calls[0].last().unwrap();
+    assert!(query.contains("project:proj after:2024-06-01 before:2024-07-01 branch:main"), "{}", query);
+    assert!(calls[0].iter().any(|a| a == "--all-approvals"));
+
+    let req = StatsRequest { until: Some("2024-05-01".to_string()), ..req };
+    assert!(stats::run(&req, Some(SSH_URL)).is_err());
+}
+
+#[test]
+fn stats_over_rest() {
+    let _env = FakeGerrit::new().install();
+    let (url, requests) = HttpStub::new()
+        .route("GET", &["/changes/?q=", "after%3A2024-06-01"], 200, &fixture("rest/stats.json"))
+        .start("proj");
+
+    let conn = change(&url, "I401").conn;
+    let reviews = gerrit_if::query_all_reviews(&conn, OPTIONS, "after:2024-06-01", 100).unwrap();
+    let found: Vec<(String, usize, usize, Option<i64>, usize)> = stats::collect(&reviews).iter()
+        .map(|s| (s.user.account(), s.reviews, s.votes, s.median_first_review(), s.open_queue))
+        .collect();
+    assert_eq!(found, vec![
+        ("alice".to_string(), 1, 1, Some(3600), 0),
+        ("1005".to_string(), 1, 1, Some(600), 0),
+    ]);
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    assert!(requests[0].path.contains("o=MESSAGES") && requests[0].path.contains("o=DETAILED_LABELS"), "{}", requests[0].path);
+}
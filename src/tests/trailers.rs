@@ -0,0 +1,20 @@
+use crate::git;
+
+#[test]
+fn parse_reviewer_trailers() {
+    let block = "Reviewer: alice\nreviewer: Alice Doe <alice.doe@example.com>\nCC: @team\nSigned-off-by: Bob <bob@example.com>\nChange-Id: I1234abcd\n\n";
+    let trailers = git::parse_trailers(block);
+    assert_eq!(trailers.change_id, "I1234abcd");
+    assert_eq!(trailers.reviewers, vec!["alice", "alice.doe@example.com"]);
+    assert_eq!(trailers.ccs, vec!["@team"]);
+}
+
+#[test]
+fn parse_trailers_keeps_first_change_id() {
+    let trailers = git::parse_trailers("Change-Id: I1111\nChange-Id: I2222\n");
+    assert_eq!(trailers.change_id, "I1111");
+    assert!(trailers.reviewers.is_empty() && trailers.ccs.is_empty());
+
+    let trailers = git::parse_trailers("");
+    assert_eq!(trailers.change_id, "");
+}
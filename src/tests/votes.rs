@@ -0,0 +1,51 @@
+use crate::votes;
+
+use super::harness::{FakeGerrit, HttpStub, SSH_URL, change, fixture};
+
+fn summary(rows: &[votes::ReviewerVotes]) -> Vec<(String, Vec<String>, bool)> {
+    rows.iter()
+        .map(|r| (r.user.account(), r.votes.iter().map(|v| v.describe()).collect(), r.commented))
+        .collect()
+}
+
+fn strings(s: &[&str]) -> Vec<String> {
+    s.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn votes_over_ssh() {
+    let env = FakeGerrit::new().on_query(&["--all-approvals", "I123"], "ssh/votes.json").install();
+
+    let rows = votes::get_reviewer_votes(&change(SSH_URL, "I123")).unwrap();
+    assert_eq!(summary(&rows), vec![
+        ("carol".to_string(), strings(&["Code-Review+2", "API-Review+1"]), true),
+        ("alice".to_string(), strings(&["Code-Review+1 (stale, PS1)"]), false),
+        ("dave".to_string(), vec![], true),
+        // Voted without being a reviewer, the vote of the older patch set is replaced
+        ("ci-bot".to_string(), strings(&["Verified+1"]), false),
+    ]);
+
+    let args = &env.gerrit_calls("query")[0];
+    for o in ["--all-reviewers", "--current-patch-set", "--all-approvals", "--comments"] {
+        assert!(args.iter().any(|a| a == o), "{} missing in {:?}", o, args);
+    }
+}
+
+#[test]
+fn votes_over_rest() {
+    let _env = FakeGerrit::new().install();
+    let (url, requests) = HttpStub::new()
+        .route("GET", &["/changes/?q=", "I123"], 200, &fixture("rest/votes.json"))
+        .start("proj");
+
+    let rows = votes::get_reviewer_votes(&change(&url, "I123")).unwrap();
+    assert_eq!(summary(&rows), vec![
+        ("carol".to_string(), strings(&["Code-Review+2"]), true),
+        ("alice".to_string(), strings(&["Code-Review+1 (stale, PS1)"]), false),
+    ]);
+
+    let path = &requests.lock().unwrap()[0].path;
+    for o in ["o=DETAILED_LABELS", "o=CURRENT_REVISION", "o=MESSAGES"] {
+        assert!(path.contains(o), "{} missing in {}", o, path);
+    }
+}
@@ -1,12 +1,21 @@
-use std::io;
-use log::{info, error};
-
-use crate::gerrit_if;
-use crate::gerrit_if::{GerritChange, GitUrl, Gerrit};
-use crate::args::Args;
-use crate::groups;
-use crate::users::GerritUser;
-use crate::config;
+use log::info;
+
+use git_reviewers::gerrit_if;
+use git_reviewers::gerrit_if::{GerritChange, GitUrl};
+use git_reviewers::selection;
+use git_reviewers::availability::Availability;
+use git_reviewers::candidates::{CandidateFilter, UserRow};
+use git_reviewers::os;
+use git_reviewers::plan::ReviewerPlan;
+use git_reviewers::journal;
+use git_reviewers::inbox;
+use git_reviewers::requirements;
+use git_reviewers::votes;
+use git_reviewers::users::GerritUser;
+use git_reviewers::config::ConfigScope;
+
+use crate::args::{Args, GroupAction};
+use crate::report;
 
 trait Option {
     fn action(&self, change: &GerritChange);
@@ -15,22 +24,26 @@ trait Option {
     }
 }
 
-pub struct TexUI {
-    pub gerrit_ctx: Gerrit,
+// User of the remote url, whose reviews and inbox are shown
+fn current_user(change: &GerritChange) -> Result<GerritUser, String> {
+    match &change.conn.username {
+        Some(u) => Ok(GerritUser::from_str(u)),
+        None => Err("No username in the remote url to find your changes".to_string()),
+    }
 }
 
+pub struct TexUI;
+
 // ShowCurReviewers
 struct ShowCurReviewers;
 impl ShowCurReviewers {
     fn run(change :&GerritChange) {
-        println!("ShowCurReviewers");
-        let reviewers = gerrit_if::get_reviewers(change);
-
-        if reviewers.len() == 0 {
-            println!("* No reviewers!");
+        if report::is_plain() {
+            println!("ShowCurReviewers");
         }
-        for r in reviewers {
-            println!("* {}", r.as_string());
+        match votes::get_reviewer_votes(change) {
+            Ok(rows) => report::print_reviewer_votes(&rows),
+            Err(err) => println!("{}", err),
         }
     }
 }
@@ -45,6 +58,21 @@ impl Option for ShowCurReviewers {
     }
 }
 
+// What the change still needs
+struct ShowRequirements;
+impl Option for ShowRequirements {
+    fn action(&self, change: &GerritChange) {
+        match requirements::analyze(change) {
+            Ok(gaps) => report::print_gaps(&gaps),
+            Err(err) => println!("{}", err),
+        }
+    }
+
+    fn get_desc(&self) -> &str {
+        "Show missing submit requirements and who can satisfy them"
+    }
+}
+
 // AddReviewers
 struct AddReviewers;
 impl AddReviewers {
@@ -52,7 +80,8 @@ impl AddReviewers {
         println!("Adding reviewers");
 
         match gerrit_if::add_reviewer(change, name) {
-            Ok(_) => {
+            Ok(msg) => {
+                println!("{}", msg);
                 ShowCurReviewers::run(change);
             },
             Err(err) => println!("{}", err),
@@ -62,10 +91,20 @@ impl AddReviewers {
 
 impl Option for AddReviewers {
     fn action(&self, change: &GerritChange) {
-        let mut name = String::new();
-        println!("Input 1 reviewer name:");
-        io::stdin().read_line(&mut name).expect("Failed to read line");
-        AddReviewers::run(change, name.trim());
+        let name = report::read_line("Input 1 reviewer name:");
+
+        let filter = match CandidateFilter::new(change) {
+            Ok(f) => f,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        if let Some(reason) = filter.check(&GerritUser::from_str(&name)) {
+            println!("Skip {}: {}", name, reason);
+            return;
+        }
+        AddReviewers::run(change, &name);
     }
 
     fn get_desc(&self) -> &str {
@@ -77,15 +116,30 @@ impl Option for AddReviewers {
 struct ClearReviewers;
 impl ClearReviewers {
     fn run(change: &GerritChange) {
-        let reviewers = gerrit_if::get_reviewers(change);
+        let reviewers = match gerrit_if::get_reviewers(change) {
+            Ok(r) => r,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
 
-        if reviewers.len() == 0 {
+        if reviewers.is_empty() {
             println!("No reviewers now");
             return;
         }
 
-        match gerrit_if::delete_reviewers(change, &reviewers) {
-            Ok(_) => {
+        let mut plan = ReviewerPlan::new(change);
+        plan.removes = reviewers;
+        println!("{}", plan.describe());
+        if !report::confirm("Remove all reviewers?") {
+            println!("Cancelled");
+            return;
+        }
+
+        match gerrit_if::apply_plan(&plan) {
+            Ok(msg) => {
+                println!("{}", msg);
                 ShowCurReviewers::run(change);
             },
             Err(err) => println!("{}", err),
@@ -106,13 +160,9 @@ impl Option for ClearReviewers {
 struct ShowRecentReviews;
 impl ShowRecentReviews {
     fn run(url: &GitUrl, user: &GerritUser) {
-        let reviews = gerrit_if::get_user_recent_reviews(url, user);
-        for r in reviews {
-            let project = r["project"].as_str().unwrap();
-            let branch = r["branch"].as_str().unwrap();
-            let subject = r["subject"].as_str().unwrap();
-            let owner = r["owner"].as_object().unwrap()["username"].as_str().unwrap();
-            println!("{}/{} | {} | {}", project, branch, owner, subject);
+        match gerrit_if::get_user_recent_reviews(url, user) {
+            Ok(reviews) => report::print_reviews(&reviews),
+            Err(err) => println!("{}", err),
         }
     }
 }
@@ -120,7 +170,13 @@ impl Option for ShowRecentReviews {
     fn action(&self, change: &GerritChange) {
         // query
         // show recent for user
-        let user = GerritUser::from_str(&change.conn.username.as_ref().unwrap());
+        let user = match current_user(change) {
+            Ok(u) => u,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
         ShowRecentReviews::run(&change.conn, &user);
     }
     fn get_desc(&self) -> &str {
@@ -132,17 +188,32 @@ impl Option for ShowRecentReviews {
 struct ShowRecentReviewers;
 impl ShowRecentReviewers {
     fn run(url: &GitUrl, user: &GerritUser) {
-        let reviewers = gerrit_if::get_user_recent_reviewers(url, user);
-        for r in reviewers {
-            println!("* {}", r.as_string());
-        }
+        let reviewers = match gerrit_if::get_user_recent_reviewer_counts(url, user) {
+            Ok(r) => r,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let rows: Vec<UserRow> = reviewers.into_iter().map(|(r, count)| {
+            let mut row = UserRow::new(r, "REVIEWER");
+            row.score = Some(count as i64);
+            row
+        }).collect();
+        report::print_users(&rows);
     }
 }
 impl Option for ShowRecentReviewers {
     fn action(&self, change: &GerritChange) {
         // query
         // show recent for user
-        let user = GerritUser::from_str(&change.conn.username.as_ref().unwrap());
+        let user = match current_user(change) {
+            Ok(u) => u,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
         ShowRecentReviewers::run(&change.conn, &user);
     }
     fn get_desc(&self) -> &str {
@@ -154,35 +225,46 @@ impl Option for ShowRecentReviewers {
 struct AddFromCandidate;
 impl Option for AddFromCandidate {
     fn action(&self, change: &GerritChange) {
-        let user = GerritUser::from_str(&change.conn.username.as_ref().unwrap());
-        let cur_reviewers = gerrit_if::get_reviewers(change);
-        let mut candidates = gerrit_if::get_user_recent_reviewers(&change.conn, &user);
+        let user = match current_user(change) {
+            Ok(u) => u,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let candidates = match gerrit_if::get_user_recent_reviewers(&change.conn, &user) {
+            Ok(c) => c,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
 
         ShowCurReviewers::run(change);
 
-        // Remove exist reviewers
-        for r in cur_reviewers {
-            for (pos, c) in candidates.iter().enumerate() {
-                if r.username.eq(&c.username) {
-                    candidates.remove(pos);
-                    break;
-                }
+        let candidates = match CandidateFilter::new(change) {
+            Ok(filter) => filter.apply(candidates),
+            Err(err) => {
+                println!("{}", err);
+                return;
             }
+        };
+        if candidates.is_empty() {
+            println!("No candidates");
+            return;
         }
-        
+
+        let availability = Availability::new(&change.conn);
+        availability.prefetch(&candidates);
         println!("Candidates: ");
         for (pos, c) in candidates.iter().enumerate() {
-            println!("  {}: {}", pos + 1, c.as_string());
+            println!("  {}: {}", pos + 1, availability.describe(c));
         }
 
-        let mut input = String::new();
-        io::stdin().read_line(&mut input).expect("Failed to read line");
-
-        let mut sel = 0;
-        match input.trim().parse::<usize>() {
+        let sel = match report::read_line("").parse::<usize>() {
             Ok(_sel) => {
-                if _sel <= candidates.len() {
-                    sel = _sel - 1;
+                if _sel >= 1 && _sel <= candidates.len() {
+                    _sel - 1
                 } else {
                     println!("Invalid option");
                     return;
@@ -192,64 +274,231 @@ impl Option for AddFromCandidate {
                 println!("Invalid input");
                 return;
             }
-        }
+        };
 
-        AddReviewers::run(change, &candidates[sel].username);
+        AddReviewers::run(change, &candidates[sel].account());
     }
     fn get_desc(&self) -> &str {
         "Add reviewers from candidates"
     }
 }
 
+// Changes waiting for the user, any of them can be managed
+struct ShowInbox;
+impl Option for ShowInbox {
+    fn action(&self, change: &GerritChange) {
+        let user = match current_user(change) {
+            Ok(u) => u,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let entries = match inbox::get_inbox(&change.conn, &user) {
+            Ok(e) => e,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        report::print_inbox(&entries);
+        if entries.is_empty() {
+            return;
+        }
+
+        let input = report::read_line("Change to manage (empty to go back):");
+        if input.is_empty() {
+            return;
+        }
+        let sel = match input.parse::<usize>() {
+            Ok(sel) if sel >= 1 && sel <= entries.len() => sel - 1,
+            _ => {
+                println!("Invalid option");
+                return;
+            }
+        };
+
+        // Invalid input in the menu of the change comes back here
+        let selected = &entries[sel].change;
+        println!("Managing {} ({})", selected.change_id, selected.conn.project());
+        while TexUI.prompt(selected) {}
+        println!("Back to {}", change.change_id);
+    }
+    fn get_desc(&self) -> &str {
+        "Show my inbox and manage one of its changes"
+    }
+}
+
+// Undo the latest reviewer change
+struct UndoReviewers;
+impl Option for UndoReviewers {
+    fn action(&self, _change: &GerritChange) {
+        match journal::next_undo() {
+            Some(e) => println!("* {}", e.describe()),
+            None => {
+                println!("Nothing to undo");
+                return;
+            }
+        }
+        if !report::confirm("Undo this change?") {
+            println!("Cancelled");
+            return;
+        }
+        match journal::undo() {
+            Ok(msg) => println!("{}", msg),
+            Err(err) => println!("{}", err),
+        }
+    }
+    fn get_desc(&self) -> &str {
+        "Undo the latest reviewer change"
+    }
+}
+
 // ShowGroups
 struct ShowGroups;
 impl Option for ShowGroups {
+    fn action(&self, _change: &GerritChange) {
+        report::print_groups();
+    }
+    fn get_desc(&self) -> &str {
+        "Show all groups"
+    }
+}
+
+// Add reviewers from a group
+struct AddFromGroup;
+impl Option for AddFromGroup {
     fn action(&self, change: &GerritChange) {
-        let configs = config::get_group_configs();
+        report::print_groups();
 
-        let mut group_sum = 0;
-        for c in &configs {
-            if c.groups.len() == 0 {
-                continue;
+        let name = report::read_line("Group name:");
+        let filter = match CandidateFilter::new(change) {
+            Ok(f) => f,
+            Err(err) => {
+                println!("{}", err);
+                return;
             }
-            println!("Config: {}", &c.file_path);
-            for g in &c.groups {
-                group_sum += 1;
-                println!("* Group: {}", &g.name);
-                for u in &g.users {
-                    println!("  * {}", u.as_string());
-                }
+        };
+        let mut history = selection::AssignmentHistory::new();
+        let pick = match selection::pick_from_group(&name, &change.conn, &filter, &history) {
+            Ok(p) => p,
+            Err(err) => {
+                println!("{}", err);
+                return;
             }
+        };
+        for s in &pick.skipped {
+            println!("{}", s.describe());
         }
-        
-        if group_sum == 0 {
-            error!("Get no group");
+        let mut plan = ReviewerPlan::new(change);
+        plan.adds = pick.users.clone();
+        plan.groups = pick.groups.clone();
+        match gerrit_if::apply_plan(&plan) {
+            Ok(msg) => {
+                println!("{}", msg);
+                history.record(&name, &pick.users);
+                if let Err(err) = history.save() {
+                    println!("Failed to save assignment history: {}", err);
+                }
+                ShowCurReviewers::run(change);
+            },
+            Err(err) => println!("{}", err),
         }
     }
+
     fn get_desc(&self) -> &str {
-        "Show all groups"
+        "Add reviewers from a group"
     }
 }
 
-// GenRandGroups
-struct GenRandGroups;
-impl Option for GenRandGroups {
+// ManageGroups
+struct ManageGroups;
+impl ManageGroups {
+    fn read_names(prompt: &str) -> Vec<String> {
+        report::read_line(prompt)
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect()
+    }
+
+    fn read_action() -> std::option::Option<GroupAction> {
+        println!("Group actions:");
+        let descs = [
+            "Create a group",
+            "Rename a group",
+            "Delete a group",
+            "Add members",
+            "Remove members",
+            "Copy a group to the other scope",
+            "Create a group from current reviewers",
+            "Show expanded members of a group",
+        ];
+        for (pos, desc) in descs.iter().enumerate() {
+            println!("  {}: {}", pos + 1, desc);
+        }
+
+        let sel = match report::read_line("").parse::<usize>() {
+            Ok(sel) => sel,
+            Err(_) => return None,
+        };
+        if sel == 0 || sel > descs.len() {
+            return None;
+        }
+
+        let name = report::read_line("Group name:");
+        let action = match sel {
+            1 => GroupAction::Create { name, members: ManageGroups::read_names("Members:") },
+            2 => GroupAction::Rename { name, new_name: report::read_line("New name:") },
+            3 => GroupAction::Delete { name },
+            4 => GroupAction::Add { name, members: ManageGroups::read_names("Members:") },
+            5 => GroupAction::Remove { name, members: ManageGroups::read_names("Members:") },
+            6 => {
+                let to = match report::read_line("To scope (local/global):").as_str() {
+                    "local" => ConfigScope::Local,
+                    "global" => ConfigScope::Global,
+                    _ => return None,
+                };
+                GroupAction::Copy { name, to }
+            },
+            7 => GroupAction::Import { name },
+            _ => GroupAction::Expand { name, live: true },
+        };
+        Some(action)
+    }
+}
+impl Option for ManageGroups {
     fn action(&self, change: &GerritChange) {
-        let gs = groups::gen_rand_groups();
-        groups::write_config(gs);
+        let action = match ManageGroups::read_action() {
+            Some(a) => a,
+            None => {
+                println!("Invalid option");
+                return;
+            }
+        };
+
+        let scope = match report::read_line("Scope (local/global) [local]:").as_str() {
+            "global" => ConfigScope::Global,
+            _ => ConfigScope::Local,
+        };
+
+        match crate::run_group_action(action, scope, change) {
+            Ok(msg) => println!("{}", msg),
+            Err(err) => println!("{}", err),
+        }
     }
 
     fn get_desc(&self) -> &str {
-        "Generate random user groups"
+        "Manage groups"
     }
 }
 
 impl TexUI {
-    pub fn start(&mut self, arg: Args) {
+    pub fn start(&self, arg: &Args) {
         info!("Text UI init");
 
-        let change = gerrit_if::get_gerrit_change(arg);
-        
+        let change = crate::get_gerrit_change(arg);
+
         // TODO loop prompt
         loop {
             let again = self.prompt(&change);
@@ -259,19 +508,23 @@ impl TexUI {
         }
     }
 
-    fn prompt(&mut self, change: &GerritChange) -> bool {
+    fn prompt(&self, change: &GerritChange) -> bool {
 
         let mut options = Options::new();
 
         // Append the list
         options.add(Box::new(ShowCurReviewers));
+        options.add(Box::new(ShowRequirements));
         options.add(Box::new(AddReviewers));
         options.add(Box::new(ClearReviewers));
         options.add(Box::new(ShowRecentReviews));
         options.add(Box::new(ShowRecentReviewers));
+        options.add(Box::new(ShowInbox));
         options.add(Box::new(AddFromCandidate));
+        options.add(Box::new(UndoReviewers));
         options.add(Box::new(ShowGroups));
-        options.add(Box::new(GenRandGroups));
+        options.add(Box::new(AddFromGroup));
+        options.add(Box::new(ManageGroups));
 
         println!("---------------------");
         println!("Options:");
@@ -279,13 +532,15 @@ impl TexUI {
             println!("  {}: {}", pos + 1, opt.get_desc());
         }
 
-        let mut input = String::new();
-        io::stdin().read_line(&mut input).expect("Failed to read line");
-
-        match input.trim().parse::<usize>() {
+        match report::read_line("").parse::<usize>() {
             Ok(sel) => {
-                if sel <= options.list.len() {
+                if sel >= 1 && sel <= options.list.len() {
+                    // Ctrl-C during the action only cancels the action
+                    os::reset_cancelled();
                     options.list[sel - 1].action(change);
+                    if os::is_cancelled() {
+                        println!("Cancelled");
+                    }
                     true
                 } else {
                     println!("Invalid option");
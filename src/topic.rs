@@ -0,0 +1,31 @@
+use crate::gerrit_if;
+use crate::gerrit_if::GerritChange;
+use crate::users::GerritUser;
+use crate::candidates::{CandidateFilter, UserRow};
+use crate::plan::ReviewerPlan;
+
+pub fn reviewer_rows(change: &GerritChange) -> Result<Vec<UserRow>, String> {
+    let reviewers = gerrit_if::get_reviewers(change)?;
+    Ok(reviewers.into_iter().map(|r| UserRow::new(r, "REVIEWER")).collect())
+}
+
+/// Add the users who are candidates of the change as its reviewers
+pub fn add_reviewers(change: &GerritChange, names: &[String]) -> Result<String, String> {
+    let filter = CandidateFilter::new(change)?;
+    let reviewers = filter.apply(names.iter().map(|n| GerritUser::from_str(n)).collect());
+    if reviewers.is_empty() {
+        return Ok("Nothing happened".to_string());
+    }
+    gerrit_if::add_reviewers(change, &reviewers)
+}
+
+/// Plans removing all reviewers of each change, to be confirmed before they are applied
+pub fn clear_plans(changes: &[GerritChange]) -> Result<Vec<ReviewerPlan<'_>>, String> {
+    let mut plans = Vec::new();
+    for c in changes {
+        let mut plan = ReviewerPlan::new(c);
+        plan.removes = gerrit_if::get_reviewers(c)?;
+        plans.push(plan);
+    }
+    Ok(plans)
+}
@@ -0,0 +1,103 @@
+use linked_hash_set::LinkedHashSet;
+
+use crate::git;
+use crate::gerrit_if;
+use crate::gerrit_if::{GerritChange, GitUrl};
+use crate::groups::{GroupExpander, GroupRef};
+use crate::candidates::CandidateFilter;
+use crate::selection;
+use crate::users::{GerritUser, insert_user};
+use crate::plan::ReviewerPlan;
+
+/// CCs with every member of `@group` and `gerrit:group` values, as CCs
+/// are accounts
+pub fn expand_ccs(ccs: &[String], conn: &GitUrl) -> Result<Vec<String>, String> {
+    let expander = GroupExpander::new(conn, true);
+    let mut ret = LinkedHashSet::new();
+    for c in ccs {
+        match GroupRef::parse(c) {
+            Some(GroupRef::Local(g)) => {
+                for u in expander.expand(&g)?.users {
+                    ret.insert_if_absent(u.account());
+                }
+            },
+            Some(GroupRef::Gerrit(g)) => {
+                for u in gerrit_if::get_group_members(conn, &g)? {
+                    ret.insert_if_absent(u.account());
+                }
+            },
+            _ => {
+                ret.insert_if_absent(c.clone());
+            },
+        }
+    }
+    Ok(ret.into_iter().collect())
+}
+
+/// Apply the reviewers declared in the trailers of the commit to its change
+pub fn apply(git_ref: &str, url: Option<&str>, remote: &str) -> Result<String, String> {
+    let trailers = match git::get_local_commit_trailers(git_ref) {
+        Some(t) => t,
+        None => return Err(format!("Failed to read commit {}", git_ref)),
+    };
+    if trailers.change_id.is_empty() {
+        return Err(format!("No Change-Id in commit {}", git_ref));
+    }
+
+    let change = GerritChange {
+        conn: gerrit_if::get_remote_conn(url, remote)?,
+        change_id: trailers.change_id.clone(),
+    };
+    let filter = CandidateFilter::new(&change)?;
+    let mut history = selection::AssignmentHistory::new();
+
+    let mut notes = Vec::new();
+    let mut reviewers = LinkedHashSet::new();
+    let mut groups = LinkedHashSet::new();
+    let mut picked = Vec::new();
+    for r in &trailers.reviewers {
+        match GroupRef::parse(r) {
+            Some(GroupRef::Local(g)) => {
+                let pick = selection::pick_from_group(&g, &change.conn, &filter, &history)?;
+                notes.extend(pick.skipped.iter().map(|s| s.describe()));
+                for u in &pick.users {
+                    insert_user(&mut reviewers, u.clone());
+                }
+                for name in pick.groups {
+                    groups.insert_if_absent(name);
+                }
+                picked.push((g, pick.users));
+            },
+            Some(GroupRef::Gerrit(g)) => {
+                groups.insert_if_absent(g);
+            },
+            _ => {
+                let (users, skipped) = filter.partition(vec![GerritUser::from_str(r)]);
+                notes.extend(skipped.iter().map(|s| s.describe()));
+                for u in users {
+                    insert_user(&mut reviewers, u);
+                }
+            },
+        }
+    }
+
+    let mut plan = ReviewerPlan::new(&change);
+    plan.adds = reviewers.into_iter().collect();
+    plan.groups = groups.into_iter().collect();
+    for c in expand_ccs(&trailers.ccs, &change.conn)? {
+        plan.ccs.push(GerritUser::from_string(c));
+    }
+    if plan.is_empty() {
+        notes.push(format!("No reviewers to add to {}", change.change_id));
+        return Ok(notes.join("\n"));
+    }
+    notes.push(gerrit_if::apply_plan(&plan)?);
+
+    for (g, users) in picked {
+        history.record(&g, &users);
+    }
+    if let Err(err) = history.save() {
+        notes.push(format!("Failed to save assignment history: {}", err));
+    }
+    Ok(notes.join("\n"))
+}
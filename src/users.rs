@@ -5,11 +5,13 @@ use std::hash::{Hash, Hasher};
 use regex::Regex;
 use linked_hash_set::LinkedHashSet;
  
-#[derive(Eq, Clone)]
+#[derive(Eq, Clone, Debug)]
 pub struct GerritUser {
     pub email: String,
     pub full_name: String,
     pub username: String,
+    /// `_account_id` of REST results, the only identity of accounts without username
+    pub account_id: Option<u64>,
 }
 
 pub type UsersTy = LinkedHashSet<GerritUser>;
@@ -17,63 +19,214 @@ pub type UsersTy = LinkedHashSet<GerritUser>;
 impl GerritUser {
     pub fn as_string(&self) -> String {
         let mut s = String::new();
-        if self.full_name.len() > 0 {
+        if !self.full_name.is_empty() {
             s.push_str(&self.full_name);
         }
-        if self.username.len() > 0 {
-            if s.len() == 0 {
+        if !self.username.is_empty() {
+            if s.is_empty() {
                 s.push_str(&self.username);
             } else {
                 s.push_str(&format!("({})", self.username));
             }
         }
-        if self.email.len() > 0 {
+        if s.is_empty() {
+            if let Some(id) = self.account_id {
+                s.push_str(&id.to_string());
+            }
+        }
+        if !self.email.is_empty() {
             s.push_str(&format!(" [{}]", self.email));
         }
         s
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(set_name: &str) -> GerritUser {
         GerritUser {
             username: set_name.to_string(),
             email: String::new(),
             full_name: String::new(),
+            account_id: None,
+        }
+    }
+    /// Identifier to write in group files, `Full Name <email>` if the user
+    /// was given that way
+    pub fn to_identifier(&self) -> UserIdentifier {
+        if !self.full_name.is_empty() && !self.email.is_empty() && self.username == self.email {
+            return UserIdentifier::NameEmail { name: self.full_name.clone(), email: self.email.clone() };
+        }
+        if self.username.is_empty() {
+            if let Some(id) = self.account_id {
+                return UserIdentifier::AccountId(id);
+            }
+            if !self.email.is_empty() {
+                return UserIdentifier::Email(self.email.clone());
+            }
+        }
+        UserIdentifier::parse(&self.username).unwrap_or_else(|| UserIdentifier::Username(self.username.clone()))
+    }
+
+    /// Identifier sent to Gerrit: the username, else the account id, else the email
+    pub fn account(&self) -> String {
+        if !self.username.is_empty() {
+            return self.username.clone();
+        }
+        match self.account_id {
+            Some(id) => id.to_string(),
+            None => self.email.clone(),
+        }
+    }
+
+    pub fn key(&self) -> UserKey {
+        if let Some(id) = self.account_id {
+            return UserKey::AccountId(id);
+        }
+        if !self.email.is_empty() {
+            return UserKey::Email(self.email.to_lowercase());
+        }
+        UserKey::Username(self.username.clone())
+    }
+
+    /// Same account id if both are known, else same email if both are known,
+    /// else same username. Users from different sources may share only one of
+    /// them, which makes this match loose and not transitive, unlike `==`
+    pub fn same_account(&self, other: &GerritUser) -> bool {
+        if let (Some(a), Some(b)) = (self.account_id, other.account_id) {
+            return a == b;
         }
+        if !self.email.is_empty() && !other.email.is_empty() {
+            return self.email.eq_ignore_ascii_case(&other.email);
+        }
+        self.username.eq(&other.username)
     }
+
     pub fn from_string(set_name: String) -> GerritUser {
         GerritUser {
             username: set_name,
             email: String::new(),
             full_name: String::new(),
+            account_id: None,
         }
     }
 }
 impl fmt::Display for GerritUser {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.username)
+        write!(f, "{}", self.account())
     }
 }
 
+/// Identity of a user in sets and maps: the account id when known, else the
+/// email, else the username
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub enum UserKey {
+    AccountId(u64),
+    Email(String),
+    Username(String),
+}
+
 impl Hash for GerritUser {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.username.hash(state);
+        self.key().hash(state);
     }
 }
 impl PartialEq for GerritUser {
     fn eq(&self, other: &Self) -> bool {
-        self.username.eq(&other.username)
+        self.key() == other.key()
     }
 }
 
-pub fn is_valid_username(name: &str) -> bool {
-    // Check for non ascii
-    let non_ascii_matcher = Regex::new(r"[[:^ascii:]]").unwrap();
-    if non_ascii_matcher.find(name).is_some() {
+/// Insert the user unless the same account is already in the set, for users
+/// from different sources, e.g. group files and query results
+pub fn insert_user(users: &mut UsersTy, user: GerritUser) -> bool {
+    if users.iter().any(|u| u.same_account(&user)) {
         return false;
     }
-    // Check for space?
+    users.insert(user)
+}
+
+// Gerrit usernames: ASCII letters, digits and "._@-", other names go in
+// the `Full Name <email>` form
+const USERNAME_PATTERN: &str = r"^[a-zA-Z0-9._@-]+$";
+const EMAIL_PATTERN: &str = r"^[^\s@<>,\x22]+@[^\s@<>,\x22]+$";
+const ACCOUNT_ID_PATTERN: &str = r"^[0-9]+$";
+const NAME_EMAIL_PATTERN: &str = r"^(.*?)\s*<([^<>]+)>$";
+
+/// Ways Gerrit accepts to identify an account
+#[derive(PartialEq, Debug)]
+pub enum UserIdentifier {
+    Username(String),
+    Email(String),
+    AccountId(u64),
+    /// `Full Name <email>`, identified by the email
+    NameEmail { name: String, email: String },
+}
+
+impl UserIdentifier {
+    pub fn parse(s: &str) -> Option<UserIdentifier> {
+        let s = s.trim();
+        if Regex::new(ACCOUNT_ID_PATTERN).unwrap().is_match(s) {
+            return s.parse().ok().map(UserIdentifier::AccountId);
+        }
+        if is_valid_email(s) {
+            return Some(UserIdentifier::Email(s.to_string()));
+        }
+        if let Some(caps) = Regex::new(NAME_EMAIL_PATTERN).unwrap().captures(s) {
+            let name = caps.get(1).unwrap().as_str();
+            let email = caps.get(2).unwrap().as_str();
+            if !is_valid_email(email) || name.contains(['<', '>']) {
+                return None;
+            }
+            return Some(UserIdentifier::NameEmail { name: name.to_string(), email: email.to_string() });
+        }
+        if is_valid_username(s) {
+            return Some(UserIdentifier::Username(s.to_string()));
+        }
+        None
+    }
+
+    /// The username of the user is what is sent to Gerrit, i.e. the email
+    /// for `Email` and `NameEmail`, the account id has no username
+    pub fn to_user(&self) -> GerritUser {
+        match self {
+            UserIdentifier::Username(u) => GerritUser::from_str(u),
+            UserIdentifier::AccountId(id) => GerritUser {
+                account_id: Some(*id),
+                ..GerritUser::from_str("")
+            },
+            UserIdentifier::Email(e) => GerritUser {
+                username: e.clone(),
+                email: e.clone(),
+                full_name: String::new(),
+                account_id: None,
+            },
+            UserIdentifier::NameEmail { name, email } => GerritUser {
+                username: email.clone(),
+                email: email.clone(),
+                full_name: name.clone(),
+                account_id: None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for UserIdentifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UserIdentifier::Username(u) => write!(f, "{}", u),
+            UserIdentifier::Email(e) => write!(f, "{}", e),
+            UserIdentifier::AccountId(id) => write!(f, "{}", id),
+            UserIdentifier::NameEmail { name, email } if name.is_empty() => write!(f, "<{}>", email),
+            UserIdentifier::NameEmail { name, email } => write!(f, "{} <{}>", name, email),
+        }
+    }
+}
+
+pub fn is_valid_username(name: &str) -> bool {
+    Regex::new(USERNAME_PATTERN).unwrap().is_match(name)
+}
 
-    return true;
+pub fn is_valid_email(email: &str) -> bool {
+    Regex::new(EMAIL_PATTERN).unwrap().is_match(email)
 }
 
 pub fn get_git_user(json_val: &serde_json::Value) -> GerritUser {
@@ -81,6 +234,7 @@ pub fn get_git_user(json_val: &serde_json::Value) -> GerritUser {
         username: json_val["username"].as_str().unwrap_or_default().to_string(),
         email: json_val["email"].as_str().unwrap_or_default().to_string(),
         full_name: json_val["name"].as_str().unwrap_or_default().to_string(),
+        account_id: json_val["_account_id"].as_u64(),
     };
-    return user;
+    user
 }
@@ -0,0 +1,146 @@
+use serde_json::Value;
+
+use crate::gerrit_if;
+use crate::gerrit_if::GerritChange;
+use crate::users;
+use crate::users::GerritUser;
+
+use serde_json::Value as Review;
+
+/// Latest non-zero vote of a user on a label
+#[derive(Clone, PartialEq, Debug)]
+pub struct Vote {
+    pub label: String,
+    pub value: i64,
+    pub patch_set: Option<u64>,
+    /// Given on an older patch set and not on the current one
+    pub stale: bool,
+}
+
+impl Vote {
+    pub fn describe(&self) -> String {
+        let mut s = format!("{}{:+}", self.label, self.value);
+        if self.stale {
+            match self.patch_set {
+                Some(n) => s.push_str(&format!(" (stale, PS{})", n)),
+                None => s.push_str(" (stale)"),
+            }
+        }
+        s
+    }
+}
+
+/// Reviewer with the votes on each label, in the order labels were first voted
+pub struct ReviewerVotes {
+    pub user: GerritUser,
+    pub votes: Vec<Vote>,
+    /// Wrote a message or inline comments, not only voted
+    pub commented: bool,
+}
+
+struct Approval {
+    user: GerritUser,
+    label: String,
+    value: i64,
+    patch_set: Option<u64>,
+    granted: i64,
+}
+
+// Numbers are strings in older Gerrit
+fn as_number(v: &Value) -> Option<u64> {
+    v.as_u64().or_else(|| v.as_str().and_then(|s| s.parse().ok()))
+}
+
+fn approvals(review: &Review, current: Option<u64>) -> Vec<Approval> {
+    let mut ret = Vec::new();
+    let mut add = |a: &Value, patch_set: Option<u64>| {
+        ret.push(Approval {
+            user: users::get_git_user(&a["by"]),
+            label: a["type"].as_str().unwrap_or_default().to_string(),
+            value: a["value"].as_str().and_then(|v| v.parse().ok()).or_else(|| a["value"].as_i64()).unwrap_or(0),
+            patch_set,
+            granted: a["grantedOn"].as_i64().unwrap_or(0),
+        });
+    };
+    for ps in review["patchSets"].as_array().into_iter().flatten() {
+        let number = as_number(&ps["number"]).or(current);
+        for a in ps["approvals"].as_array().into_iter().flatten() {
+            add(a, number);
+        }
+    }
+    for a in review["currentPatchSet"]["approvals"].as_array().into_iter().flatten() {
+        add(a, current);
+    }
+    ret
+}
+
+// "Patch Set 2: Code-Review+1" alone is a vote, anything more is a comment
+fn is_comment(message: &str) -> bool {
+    let mut lines = message.lines();
+    let first = lines.next().unwrap_or_default();
+    if !first.starts_with("Patch Set ") {
+        return !first.trim().is_empty() && !first.starts_with("Uploaded patch set");
+    }
+    lines.any(|l| !l.trim().is_empty())
+}
+
+fn entry<'a>(rows: &'a mut Vec<ReviewerVotes>, user: &GerritUser) -> &'a mut ReviewerVotes {
+    let pos = match rows.iter().position(|r| r.user == *user) {
+        Some(pos) => pos,
+        None => {
+            rows.push(ReviewerVotes { user: user.clone(), votes: Vec::new(), commented: false });
+            rows.len() - 1
+        },
+    };
+    &mut rows[pos]
+}
+
+/// Votes of the reviewers, and of anyone else who voted, from a query result
+/// with the current patch set, all approvals and comments
+pub fn from_review(review: &Review) -> Vec<ReviewerVotes> {
+    let current = as_number(&review["currentPatchSet"]["number"]);
+
+    let mut rows = Vec::new();
+    for reviewer in review["allReviewers"].as_array().into_iter().flatten() {
+        entry(&mut rows, &users::get_git_user(reviewer));
+    }
+
+    let all = approvals(review, current);
+    for a in &all {
+        let row = entry(&mut rows, &a.user);
+        if row.votes.iter().any(|v| v.label == a.label) {
+            continue;
+        }
+        let on_label: Vec<&Approval> = all.iter()
+            .filter(|b| b.user == a.user && b.label == a.label && b.value != 0)
+            .collect();
+        let latest = |stale: bool| on_label.iter()
+            .filter(|b| (b.patch_set == current) != stale)
+            .max_by_key(|b| (b.patch_set, b.granted))
+            .map(|b| Vote { label: b.label.clone(), value: b.value, patch_set: b.patch_set, stale });
+        // Older votes only count when there is none on the current patch set
+        let vote = latest(false).or_else(|| if current.is_some() { latest(true) } else { None });
+        if let Some(v) = vote {
+            row.votes.push(v);
+        }
+    }
+
+    for c in review["comments"].as_array().into_iter().flatten() {
+        if is_comment(c["message"].as_str().unwrap_or_default()) {
+            let author = users::get_git_user(&c["reviewer"]);
+            if let Some(row) = rows.iter_mut().find(|r| r.user == author) {
+                row.commented = true;
+            }
+        }
+    }
+    rows
+}
+
+pub fn get_reviewer_votes(change: &GerritChange) -> Result<Vec<ReviewerVotes>, String> {
+    let options = ["--all-reviewers", "--current-patch-set", "--all-approvals", "--comments"];
+    let reviews = gerrit_if::query_reviews(&change.conn, &options, &change.change_id)?;
+    match reviews.first() {
+        Some(r) => Ok(from_review(r)),
+        None => Err(format!("Change {} not found", change.change_id)),
+    }
+}